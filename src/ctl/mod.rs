@@ -0,0 +1,316 @@
+use std::{
+    env,
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+    process,
+};
+
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+
+use crate::script::{config::Config, window::Notice, Control, Snapshot, Status};
+
+/// 控制指令, 通过本地 socket 每行发送一个 JSON 请求, 如 `{"cmd":"start","title":"脚本"}`
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Request {
+    /// 列出所有脚本及是否运行中
+    List,
+    /// 启动脚本
+    Start { title: String },
+    /// 终止脚本
+    Stop { title: String },
+    /// 终止所有运行中的脚本
+    StopAll,
+    /// 重新加载配置
+    Reload,
+    /// 查询进程, 方案和层等状态
+    Status,
+}
+
+/// 控制指令的回复, ok 表示是否成功, 其余字段见 [`Body`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(flatten)]
+    pub body: Body,
+}
+
+impl Reply {
+    /// 成功的回复
+    pub fn ok(body: Body) -> Self {
+        Self { ok: true, body }
+    }
+
+    /// 失败的回复
+    pub fn error(err: impl ToString) -> Self {
+        Self { ok: false, body: Body::Error { error: err.to_string() } }
+    }
+}
+
+impl Display for Reply {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// 回复中除 ok 以外的字段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Body {
+    Error {
+        error: String,
+    },
+    /// list 的回复
+    Scripts {
+        scripts: Vec<ScriptInfo>,
+    },
+    /// status 的回复
+    Status {
+        pid: u32,
+        config: String,
+        profile: Option<String>,
+        layers: Vec<String>,
+        running: Vec<String>,
+    },
+    /// 当前的配置
+    Config {
+        config: serde_json::Value,
+    },
+    Empty {},
+}
+
+/// 脚本的运行状态, state 为 idle, running 或 looping; 有触发按键时附带 trigger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptInfo {
+    pub title: String,
+    pub running: bool,
+    pub state: String,
+    pub iteration: usize,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<String>,
+}
+
+impl ScriptInfo {
+    pub fn new(status: &Status, trigger: Option<&str>) -> Self {
+        Self {
+            title: status.title.to_string(),
+            running: status.running(),
+            state: status.state.name().to_string(),
+            iteration: status.iteration,
+            error: status.error.as_deref().cloned(),
+            trigger: trigger.map(str::to_string),
+        }
+    }
+}
+
+impl Display for ScriptInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// 处理控制指令, 把请求转为 [`Control`] 发送给脚本列表
+#[derive(Debug, Clone)]
+pub struct Handler {
+    /// 配置文件, 用于 reload
+    pub config: PathBuf,
+    pub control: UnboundedSender<Control>,
+    pub updater: UnboundedSender<Status>,
+    pub notifier: UnboundedSender<Notice>,
+}
+
+impl Handler {
+    pub async fn handle(&self, request: Request) -> Reply {
+        let empty = |_| Reply::ok(Body::Empty {});
+        let res = match request {
+            Request::List => self.send(Control::Status).await.map(|snapshot: Snapshot| {
+                let scripts = snapshot
+                    .scripts
+                    .iter()
+                    .map(|(status, trigger)| ScriptInfo::new(status, Some(trigger)));
+                Reply::ok(Body::Scripts { scripts: scripts.collect() })
+            }),
+            Request::Status => self.send(Control::Status).await.map(|snapshot: Snapshot| {
+                let running = snapshot.scripts.into_iter().filter(|(status, _)| status.running());
+                Reply::ok(Body::Status {
+                    pid: process::id(),
+                    config: self.config.to_string_lossy().into_owned(),
+                    profile: snapshot.profile.map(|p| p.to_string()),
+                    layers: snapshot.layers,
+                    running: running.map(|(status, _)| status.title.to_string()).collect(),
+                })
+            }),
+            Request::Start { title } => self
+                .send(|reply| Control::Start(title, reply))
+                .await
+                .and_then(|res| res.map(empty)),
+            Request::Stop { title } => self
+                .send(|reply| Control::Stop(title, reply))
+                .await
+                .and_then(|res| res.map(empty)),
+            Request::StopAll => self.send(Control::StopAll).await.map(empty),
+            Request::Reload => Config::reload(&self.config, &self.control, &self.updater, &self.notifier).map(empty),
+        };
+        res.unwrap_or_else(Reply::error)
+    }
+
+    /// 发送指令给脚本列表并等待回复
+    async fn send<T>(&self, control: impl FnOnce(oneshot::Sender<T>) -> Control) -> Result<T, String> {
+        let stopped = || "脚本列表已停止".to_string();
+        let (tx, rx) = oneshot::channel();
+        self.control.send(control(tx)).map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())
+    }
+}
+
+/// 默认的 socket 路径: $XDG_RUNTIME_DIR/kmm.sock, 没有时为临时目录下的 kmm-{uid}.sock
+pub fn default_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("kmm.sock"),
+        #[cfg(unix)]
+        None => env::temp_dir().join(format!("kmm-{}.sock", unsafe { libc::getuid() })),
+        #[cfg(not(unix))]
+        None => env::temp_dir().join("kmm.sock"),
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{request, Server};
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        error::Error,
+        fs,
+        io::{self, BufRead, BufReader, Write},
+        os::unix::{fs::PermissionsExt, net},
+        path::{Path, PathBuf},
+    };
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader},
+        net::{UnixListener, UnixStream},
+    };
+
+    use crate::{
+        ctl::{Handler, Reply, Request},
+        script::window::Notice,
+    };
+
+    /// Unix socket 上的控制接口
+    #[derive(Debug, Clone)]
+    pub struct Server {
+        pub path: PathBuf,
+        pub handler: Handler,
+    }
+
+    impl Server {
+        /// 监听 socket, 只有当前用户可以连接
+        pub async fn serve(self) -> io::Result<()> {
+            // 单实例运行, 已存在的只会是上次退出时残留的
+            let _ = fs::remove_file(&self.path);
+            let listener = UnixListener::bind(&self.path)?;
+            let _socket = SocketFile(&self.path);
+            // 开始接受连接前改为只有当前用户可读写
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let server = self.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = server.connection(stream).await {
+                        let _ = server
+                            .handler
+                            .notifier
+                            .send(Notice::Message(format!("控制连接出错: {err}")));
+                    }
+                });
+            }
+        }
+
+        /// 逐行处理请求, 每个请求回复一行
+        async fn connection(&self, stream: UnixStream) -> io::Result<()> {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = AsyncBufReader::new(reader).lines();
+            while let Some(line) = lines.next_line().await? {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = match serde_json::from_str(&line) {
+                    Ok(request) => self.handler.handle(request).await,
+                    Err(err) => Reply::error(format!("请求 {line:?} 有误: {err}")),
+                };
+                writer.write_all(format!("{response}\n").as_bytes()).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// 监听结束时删除 socket 文件
+    struct SocketFile<'a>(&'a Path);
+
+    impl Drop for SocketFile<'_> {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.0);
+        }
+    }
+
+    /// 发送请求并等待回复
+    pub fn request(path: &Path, request: &Request) -> Result<Reply, Box<dyn Error>> {
+        let mut stream =
+            net::UnixStream::connect(path).map_err(|err| format!("无法连接 {path:?}, kmm 是否在运行: {err}"))?;
+        writeln!(stream, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_format() {
+        let start = Request::Start { title: "脚本".into() };
+        assert_eq!(
+            serde_json::to_string(&start).unwrap(),
+            r#"{"cmd":"start","title":"脚本"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Request::StopAll).unwrap(),
+            r#"{"cmd":"stop-all"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"cmd":"list"}"#).unwrap(),
+            Request::List
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"cmd":"start"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"cmd":"nope"}"#).is_err());
+    }
+
+    #[test]
+    fn reply_format() {
+        let replies = [
+            (Reply::ok(Body::Empty {}), r#"{"ok":true}"#),
+            (Reply::error("失败"), r#"{"ok":false,"error":"失败"}"#),
+            (
+                Reply::ok(Body::Status {
+                    pid: 1,
+                    config: "config.toml".into(),
+                    profile: None,
+                    layers: vec![],
+                    running: vec!["A".into()],
+                }),
+                r#"{"ok":true,"pid":1,"config":"config.toml","profile":null,"layers":[],"running":["A"]}"#,
+            ),
+        ];
+        for (reply, json) in replies {
+            assert_eq!(reply.to_string(), json);
+            assert_eq!(serde_json::from_str::<Reply>(json).unwrap(), reply);
+        }
+    }
+}
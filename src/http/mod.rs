@@ -0,0 +1,293 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Request as HttpRequest, State,
+    },
+    http::{header, uri::Authority, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        broadcast::{self, error::RecvError},
+        oneshot,
+    },
+};
+
+use crate::{
+    ctl::{Body, Handler, Reply, Request, ScriptInfo},
+    script::{config::Config, window::Notice, Control, Status},
+};
+
+/// 控制面板的页面
+const INDEX: &str = include_str!("index.html");
+
+/// HTTP 控制面板: 页面, 控制脚本的 REST 接口和推送运行状态的 WebSocket
+///
+/// - `GET /api/scripts` 脚本列表, `GET /api/status` 状态, `GET /api/config` 解析后的配置
+/// - `POST /api/scripts/{title}/start`, `POST /api/scripts/{title}/stop`, `POST /api/stop-all`, `POST /api/reload`
+/// - `GET /ws` 先推送所有脚本的状态, 之后推送每次变化, 每条为 [`ScriptInfo`] 的格式
+///
+/// Host 或 Origin 与监听地址不符的请求返回 403, 防止其他网页通过浏览器控制脚本
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    pub addr: SocketAddr,
+    pub handler: Handler,
+    /// 脚本运行状态的变化
+    pub status: broadcast::Sender<Status>,
+}
+
+impl Dashboard {
+    pub async fn serve(self) -> io::Result<()> {
+        let listener = TcpListener::bind(self.addr).await?;
+        axum::serve(listener, self.router()).await
+    }
+
+    pub fn router(self) -> Router {
+        let handle = |request: Request| {
+            move |State(dashboard): State<Dashboard>| async move { dashboard.handler.handle(request).await }
+        };
+        Router::new()
+            .route("/", get(|| async { Html(INDEX) }))
+            .route("/ws", get(websocket))
+            .route("/api/config", get(config))
+            .route("/api/scripts", get(handle(Request::List)))
+            .route("/api/status", get(handle(Request::Status)))
+            .route("/api/scripts/:title/start", post(start))
+            .route("/api/scripts/:title/stop", post(stop))
+            .route("/api/stop-all", post(handle(Request::StopAll)))
+            .route("/api/reload", post(handle(Request::Reload)))
+            .fallback(|method: Method, uri: Uri| async move {
+                let reply = Reply::error(format!("没有 {method} {}", uri.path()));
+                (StatusCode::NOT_FOUND, Json(reply))
+            })
+            .layer(middleware::from_fn_with_state(self.clone(), check_host))
+            .with_state(self)
+    }
+
+    /// Host 是否指向监听的地址; 监听所有地址时允许任意 IP, 域名只允许 localhost, 防止 DNS 重绑定
+    fn allows(&self, host: &str) -> bool {
+        let Ok(authority) = host.parse::<Authority>() else {
+            return false;
+        };
+        if authority.port_u16().unwrap_or(80) != self.addr.port() {
+            return false;
+        }
+        let ip = self.addr.ip();
+        match authority
+            .host()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+        {
+            Ok(host) => ip.is_unspecified() || host == ip,
+            Err(_) => authority.host().eq_ignore_ascii_case("localhost") && (ip.is_unspecified() || ip.is_loopback()),
+        }
+    }
+
+    /// 推送所有脚本的状态
+    async fn snapshot(&self, socket: &mut WebSocket) -> Result<(), axum::Error> {
+        let (tx, rx) = oneshot::channel();
+        if self.handler.control.send(Control::Status(tx)).is_err() {
+            return Ok(());
+        }
+        if let Ok(snapshot) = rx.await {
+            for (status, trigger) in snapshot.scripts {
+                let info = ScriptInfo::new(&status, Some(&trigger));
+                socket.send(Message::Text(info.to_string())).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 先推送所有脚本的状态, 之后推送每次变化, 直到客户端关闭
+    async fn push(&self, mut socket: WebSocket) -> Result<(), axum::Error> {
+        // 先订阅再查询, 避免漏掉查询期间的变化
+        let mut status = self.status.subscribe();
+        self.snapshot(&mut socket).await?;
+        loop {
+            tokio::select! {
+                res = status.recv() => match res {
+                    Ok(status) => socket.send(Message::Text(ScriptInfo::new(&status, None).to_string())).await?,
+                    // 跟不上时丢掉积压的变化, 重新推送完整的状态
+                    Err(RecvError::Lagged(_)) => {
+                        status = status.resubscribe();
+                        self.snapshot(&mut socket).await?
+                    }
+                    Err(RecvError::Closed) => return socket.close().await,
+                },
+                // 只关心客户端是否关闭, 不处理其他消息
+                msg = socket.recv() => match msg {
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => return Err(err),
+                },
+            }
+        }
+    }
+}
+
+/// 成功时为 200, 失败时为 400
+impl IntoResponse for Reply {
+    fn into_response(self) -> Response {
+        let status = match self.ok {
+            true => StatusCode::OK,
+            false => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// 拒绝 Host 不是监听地址, 或 Origin 与 Host 不同的请求
+async fn check_host(State(dashboard): State<Dashboard>, request: HttpRequest, next: Next) -> Response {
+    let headers = request.headers();
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let allowed = match header(header::HOST) {
+        Some(host) if dashboard.allows(host) => {
+            header(header::ORIGIN).is_none_or(|origin| origin == format!("http://{host}"))
+        }
+        _ => false,
+    };
+    match allowed {
+        true => next.run(request).await,
+        false => (
+            StatusCode::FORBIDDEN,
+            Json(Reply::error("Host 或 Origin 与监听地址不符")),
+        )
+            .into_response(),
+    }
+}
+
+async fn config(State(dashboard): State<Dashboard>) -> Reply {
+    match Config::read(&dashboard.handler.config).and_then(|config| Ok(serde_json::to_value(config)?)) {
+        Ok(config) => Reply::ok(Body::Config { config }),
+        Err(err) => Reply::error(err),
+    }
+}
+
+async fn start(State(dashboard): State<Dashboard>, Path(title): Path<String>) -> Reply {
+    dashboard.handler.handle(Request::Start { title }).await
+}
+
+async fn stop(State(dashboard): State<Dashboard>, Path(title): Path<String>) -> Reply {
+    dashboard.handler.handle(Request::Stop { title }).await
+}
+
+async fn websocket(State(dashboard): State<Dashboard>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(err) = dashboard.push(socket).await {
+            let _ = dashboard
+                .handler
+                .notifier
+                .send(Notice::Message(format!("WebSocket 连接出错: {err}")));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::mpsc,
+    };
+
+    use super::*;
+    use crate::script::Snapshot;
+
+    /// 在随机端口启动控制面板, 脚本列表只回复 Status
+    async fn serve() -> SocketAddr {
+        let (control, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(control) = rx.recv().await {
+                if let Control::Status(reply) = control {
+                    let scripts = vec![(Status::new(Arc::new("A".into())), "F1".into())];
+                    let _ = reply.send(Snapshot { scripts, profile: None, layers: vec![] });
+                }
+            }
+        });
+        let handler = Handler {
+            config: "config.toml".into(),
+            control,
+            updater: mpsc::unbounded_channel().0,
+            notifier: mpsc::unbounded_channel().0,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dashboard = Dashboard { addr, handler, status: broadcast::channel(1).0 };
+        tokio::spawn(async move { axum::serve(listener, dashboard.router()).await });
+        addr
+    }
+
+    /// 发送 GET 请求, 返回状态码和响应体
+    async fn get(addr: SocketAddr, path: &str, headers: &str) -> (u16, String) {
+        send(
+            addr,
+            &format!("GET {path} HTTP/1.1\r\n{headers}Connection: close\r\n\r\n"),
+        )
+        .await
+    }
+
+    /// 发送原始的请求, 返回状态码和响应体
+    async fn send(addr: SocketAddr, request: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn list_scripts_over_tcp() {
+        let addr = serve().await;
+        let (status, body) = get(addr, "/api/scripts", &format!("Host: {addr}\r\n")).await;
+        assert_eq!(status, 200);
+        let reply: Reply = serde_json::from_str(&body).unwrap();
+        let Body::Scripts { scripts } = reply.body else {
+            panic!("{body}")
+        };
+        assert_eq!(scripts[0].title, "A");
+        assert_eq!(scripts[0].trigger.as_deref(), Some("F1"));
+
+        let (status, _) = get(addr, "/api/nope", &format!("Host: {addr}\r\n")).await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn reject_other_hosts_and_origins() {
+        let addr = serve().await;
+        let port = addr.port();
+        let headers = [
+            (format!("Host: localhost:{port}\r\n"), 200),
+            (format!("Host: {addr}\r\nOrigin: http://{addr}\r\n"), 200),
+            (format!("Host: evil.com:{port}\r\n"), 403),
+            (format!("Host: 127.0.0.2:{port}\r\n"), 403),
+            (format!("Host: {addr}\r\nOrigin: http://evil.com\r\n"), 403),
+            ("Host: \r\n".into(), 403),
+        ];
+        // 403 的回复来自检查 Host 的中间件, 而不是 HTTP 库
+        let rejected = |body: &str| body.contains("Host 或 Origin");
+        for (headers, expected) in headers {
+            let (status, body) = get(addr, "/api/status", &headers).await;
+            assert_eq!(status, expected, "{headers}");
+            assert_eq!(rejected(&body), expected == 403, "{body}");
+        }
+
+        // HTTP/1.0 可以没有 Host
+        let (status, body) = send(addr, "GET /api/status HTTP/1.0\r\n\r\n").await;
+        assert_eq!(status, 403);
+        assert!(rejected(&body), "{body}");
+    }
+}
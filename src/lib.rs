@@ -1,17 +1,29 @@
-use std::{
-    ops::Sub,
-    path::PathBuf,
-    process::exit,
-    time::{Duration, Instant},
-};
+use std::{path::PathBuf, process::exit, sync::Arc, time::Duration};
 
 use clap::{Parser, Subcommand};
 use rdev::{listen, Event, EventType, Key};
+use tokio::sync::mpsc;
 
-use crate::script::config::{Config, ScriptEvent};
+use crate::{
+    ctl::Request,
+    record::{optimize::Optimizer, Recorder},
+    script::{
+        backend::RdevBackend,
+        config::{Config, KeyOrButton},
+        expr::key_or_button,
+        window::Notice,
+        Runtime,
+    },
+};
 
+pub mod ctl;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod record;
 pub mod script;
 pub mod sing_app;
+#[cfg(unix)]
+pub mod tui;
 
 /// 键鼠宏脚本(无反应或需 root 启动)
 #[derive(Debug, Parser)]
@@ -25,16 +37,32 @@ impl Cli {
     pub fn run(self) {
         match self.sub_command {
             None => {
-                Run { config: PathBuf::from("./config.toml") }.run();
+                Run {
+                    config: PathBuf::from("./config.toml"),
+                    socket: None,
+                    headless: false,
+                    #[cfg(unix)]
+                    tui: false,
+                    #[cfg(feature = "http")]
+                    http: None,
+                }
+                .run();
             }
             Some(command) => match command {
                 Commands::Run(r) => r.run(),
                 Commands::Event => event(),
                 Commands::Point => point(),
-                Commands::Record => record(),
+                Commands::Record(r) => r.run(),
+                Commands::Optimize(o) => o.run(),
+                Commands::Ctl(c) => c.run(),
             },
         }
     }
+
+    /// 是否需要单实例运行; 只有运行脚本时会结束已运行的实例, 其他子命令可以和它同时使用
+    pub fn single(&self) -> bool {
+        matches!(self.sub_command, None | Some(Commands::Run(_)))
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -45,27 +73,105 @@ pub enum Commands {
     Event,
     /// 获取鼠标坐标 PS: Alt 输出当前坐标; Escape 清屏
     Point,
-    /// 录制事件
-    Record,
+    /// 录制事件, 按开始键开始, 按结束键结束并输出脚本
+    Record(Record),
+    /// 优化录制的脚本: 合并按下和松开, 简化鼠标轨迹, 取整 Sleep
+    Optimize(Optimize),
+    /// 控制运行中的实例, 输出 JSON 格式的回复
+    Ctl(Ctl),
 }
 
 #[derive(Debug, Parser)]
 pub struct Run {
     /// 配置文件所在路径
     config: PathBuf,
+    /// 控制接口的 socket 路径, 默认为 $XDG_RUNTIME_DIR/kmm.sock
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// 不显示窗口, 运行状态输出到终端(未启用 gui 特性时总是如此)
+    #[arg(long)]
+    headless: bool,
+    /// 在终端中显示脚本列表, 可以选择脚本启动或终止
+    #[cfg(unix)]
+    #[arg(long, conflicts_with = "headless")]
+    tui: bool,
+    /// 启动 HTTP 控制面板的地址, 如 127.0.0.1:8080
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    http: Option<std::net::SocketAddr>,
 }
 
 impl Run {
     fn run(self) {
-        match Config::load(self.config) {
+        // 监听和模拟按键都通过 X11, 无窗口运行也需要 X 服务
+        #[cfg(target_os = "linux")]
+        if std::env::var_os("DISPLAY").is_none_or(|display| display.is_empty()) {
+            println!("没有设置 DISPLAY, 需要在 X11 桌面(或 Xvfb 等 X 服务)中运行");
+            exit(1)
+        }
+        match Config::load(&self.config) {
             Ok((script, window)) => {
+                let (control, rx) = mpsc::unbounded_channel();
+                let handler = ctl::Handler {
+                    config: self.config.clone(),
+                    control: control.clone(),
+                    updater: window.updater.clone(),
+                    notifier: window.notifier.clone(),
+                };
+                #[cfg(unix)]
+                let socket = self.socket.unwrap_or_else(ctl::default_socket);
+                #[cfg(unix)]
+                {
+                    let server = ctl::Server { path: socket.clone(), handler: handler.clone() };
+                    let notifier = window.notifier.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = server.serve().await {
+                            let _ = notifier.send(Notice::Message(format!("启动控制接口失败: {err}")));
+                        }
+                    });
+                }
+                #[cfg(feature = "http")]
+                if let Some(addr) = self.http {
+                    let dashboard = http::Dashboard {
+                        addr,
+                        handler: handler.clone(),
+                        status: window.status.clone(),
+                    };
+                    let notifier = window.notifier.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = dashboard.serve().await {
+                            let _ = notifier.send(Notice::Message(format!("启动 HTTP 控制面板失败: {err}")));
+                        }
+                    });
+                }
+                tokio::spawn(Config::watch(
+                    self.config,
+                    control,
+                    window.updater.clone(),
+                    window.notifier.clone(),
+                ));
+                let notifier = window.notifier.clone();
                 tokio::task::spawn_blocking(move || {
-                    if let Err(err) = script.listening() {
-                        println!("监听脚本触发失败: {err:?}");
+                    if let Err(err) = script.listening(Runtime::new(Arc::new(RdevBackend::default())), rx) {
+                        let _ = notifier.send(Notice::Message(format!("监听脚本触发失败: {err:?}")));
                     }
                     std::thread::sleep(Duration::from_secs(30));
                 });
-                window.run().unwrap();
+                #[cfg(unix)]
+                if self.tui {
+                    if let Err(err) = tui::Tui::new(handler).run(window) {
+                        println!("终端界面出错: {err}");
+                    }
+                    // 监听按键的阻塞线程不会返回, 等待运行时关闭会卡住; exit 不会执行析构, 自行删除 socket
+                    let _ = std::fs::remove_file(&socket);
+                    exit(0)
+                }
+                #[cfg(feature = "gui")]
+                if !self.headless {
+                    window.run().unwrap();
+                    return;
+                }
+                window.run_headless();
             }
             Err(err) => {
                 println!("加载脚本配置失败: {err}");
@@ -75,6 +181,160 @@ impl Run {
     }
 }
 
+#[derive(Debug, Parser)]
+pub struct Record {
+    /// 输出的配置文件, 已存在时追加到末尾; 不填时输出到终端
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// 脚本标题
+    #[arg(long, default_value = "录制")]
+    title: String,
+    /// 脚本的触发按键
+    #[arg(long, default_value = "F1", value_parser = key_or_button)]
+    trigger: KeyOrButton,
+    /// 读取缩放和偏移的配置文件, 默认为已存在的输出文件
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// 开始录制的按键
+    #[arg(long, default_value = "F9", value_parser = key_or_button)]
+    start: KeyOrButton,
+    /// 结束录制的按键
+    #[arg(long, default_value = "F10", value_parser = key_or_button)]
+    stop: KeyOrButton,
+    /// 录制鼠标移动轨迹
+    #[arg(long)]
+    moves: bool,
+    /// 轨迹的采样间隔毫秒
+    #[arg(long, default_value_t = 10)]
+    sample: u64,
+    /// 轨迹点之间的最小距离(像素)
+    #[arg(long, default_value_t = 2.0)]
+    min_distance: f64,
+    /// 保存前优化录制结果
+    #[arg(long)]
+    optimize: bool,
+    #[command(flatten)]
+    optimizer: Optimizer,
+}
+
+impl Record {
+    fn run(self) {
+        if let Err(err) = self.check() {
+            println!("录制失败: {err}");
+            return;
+        }
+        let (scaling, offset) = match self.config.as_ref().or(self.out.as_ref().filter(|path| path.exists())) {
+            Some(path) => match Config::read(path) {
+                Ok(config) => (config.scaling, config.offset),
+                Err(err) => {
+                    println!("读取配置 {path:?} 失败: {err}");
+                    return;
+                }
+            },
+            None => (1.0, (0.0, 0.0)),
+        };
+
+        println!("按 {:?} 开始录制, 按 {:?} 结束", self.start, self.stop);
+        let mut recorder = Recorder::new(self.start.clone(), self.stop.clone(), scaling, offset);
+        if self.moves {
+            recorder = recorder.with_moves(Duration::from_millis(self.sample), self.min_distance);
+        }
+        let callback = move |event: Event| {
+            let was = recorder.recording();
+            if !recorder.handle(&event) {
+                if !was && recorder.recording() {
+                    println!("开始录制");
+                }
+                return;
+            }
+            let events = match self.optimize {
+                true => self.optimizer.run(recorder.events().to_vec()),
+                false => recorder.events().to_vec(),
+            };
+            match record::save(self.out.as_deref(), &self.title, &self.trigger, &events) {
+                Ok(()) => println!("录制结束, 共 {} 个事件", events.len()),
+                Err(err) => println!("保存录制失败: {err}"),
+            }
+            exit(0);
+        };
+        let _ = listen(callback);
+    }
+
+    /// 追加到已有配置时标题不能重复
+    fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(out) = self.out.as_ref().filter(|path| path.exists()) else {
+            return Ok(());
+        };
+        let config = Config::read(out)?;
+        match config.scripts.iter().any(|item| item.title == self.title) {
+            true => Err(format!("{out:?} 中已有标题为 {:?} 的脚本", self.title).into()),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Optimize {
+    /// 配置文件所在路径
+    config: PathBuf,
+    /// 输出的配置文件, 可以和输入相同; 不填时输出到终端
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// 只优化指定标题的脚本
+    #[arg(long)]
+    title: Option<String>,
+    #[command(flatten)]
+    optimizer: Optimizer,
+}
+
+impl Optimize {
+    fn run(self) {
+        let res = record::optimize(
+            &self.config,
+            self.out.as_deref(),
+            self.title.as_deref(),
+            &self.optimizer,
+        );
+        if let Err(err) = res {
+            println!("优化失败: {err}");
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct Ctl {
+    /// 控制接口的 socket 路径, 默认为 $XDG_RUNTIME_DIR/kmm.sock
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    #[command(subcommand)]
+    request: Request,
+}
+
+impl Ctl {
+    #[cfg(unix)]
+    fn run(self) {
+        let path = self.socket.unwrap_or_else(ctl::default_socket);
+        match ctl::request(&path, &self.request) {
+            Ok(reply) => {
+                println!("{reply}");
+                if !reply.ok {
+                    exit(1)
+                }
+            }
+            Err(err) => {
+                println!("{}", ctl::Reply::error(err));
+                exit(1)
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn run(self) {
+        println!("{}", ctl::Reply::error("当前平台暂不支持控制接口"));
+        exit(1)
+    }
+}
+
 /// 获取事件代码
 fn event() {
     fn callback(event: Event) {
@@ -108,42 +368,3 @@ fn point() {
     };
     let _ = listen(callback);
 }
-
-/// 录制事件
-fn record() {
-    let mut point = (0.0, 0.0);
-    let mut prev = Instant::now();
-    let mut res = vec![];
-    let callback = move |event: Event| {
-        if let EventType::MouseMove { x, y } = event.event_type {
-            if x + y < 1_f64 {
-                for item in res.iter() {
-                    println!("{}", toml::to_string_pretty(&item).unwrap())
-                }
-                exit(0);
-            }
-            point = (x, y);
-            return;
-        }
-
-        let curr = Instant::now();
-        res.push(ScriptEvent::Sleep(curr.sub(prev).as_millis() as u64));
-        prev = curr;
-
-        match event.event_type {
-            EventType::KeyPress(key) => res.push(ScriptEvent::KeyDown(key)),
-            EventType::KeyRelease(key) => res.push(ScriptEvent::KeyUp(key)),
-            EventType::ButtonPress(button) => {
-                res.push(ScriptEvent::Move(point.0, point.1));
-                res.push(ScriptEvent::ClickDown(button))
-            }
-            EventType::ButtonRelease(button) => {
-                res.push(ScriptEvent::Move(point.0, point.1));
-                res.push(ScriptEvent::ClickUp(button))
-            }
-            EventType::Wheel { delta_x, delta_y } => res.push(ScriptEvent::Scroll(delta_x, delta_y)),
-            _ => {}
-        }
-    };
-    let _ = listen(callback);
-}
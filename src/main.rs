@@ -3,7 +3,7 @@ use kmm::{sing_app::SingApp, Cli};
 
 #[tokio::main]
 async fn main() {
-    let _app = SingApp::run_current().unwrap();
     let cli = Cli::parse();
+    let _app = cli.single().then(|| SingApp::run_current().unwrap());
     cli.run()
 }
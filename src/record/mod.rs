@@ -0,0 +1,255 @@
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::Write,
+    mem,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use rdev::{Event, EventType};
+
+use crate::{
+    record::optimize::Optimizer,
+    script::config::{Config, KeyOrButton, Num, ScriptEvent},
+};
+
+pub mod optimize;
+
+/// 录制键鼠事件
+#[derive(Debug)]
+pub struct Recorder {
+    /// 开始录制的按键
+    start: KeyOrButton,
+    /// 结束录制的按键
+    stop: KeyOrButton,
+    /// 缩放和偏移, 用于把屏幕位置换算为配置中的位置
+    scaling: f64,
+    offset: (f64, f64),
+    /// 录制鼠标轨迹的采样间隔和最小距离, None 为不录制
+    moves: Option<(Duration, f64)>,
+    recording: bool,
+    /// 鼠标当前位置
+    point: (f64, f64),
+    /// 上一个录制的事件或轨迹点的鼠标位置
+    last: (f64, f64),
+    /// 上一个事件的时间
+    prev: Instant,
+    /// 录制中的轨迹
+    path: Vec<(u64, Num, Num)>,
+    events: Vec<ScriptEvent>,
+}
+
+impl Recorder {
+    pub fn new(start: KeyOrButton, stop: KeyOrButton, scaling: f64, offset: (f64, f64)) -> Self {
+        Self {
+            start,
+            stop,
+            scaling,
+            offset,
+            moves: None,
+            recording: false,
+            point: (0.0, 0.0),
+            last: (0.0, 0.0),
+            prev: Instant::now(),
+            path: vec![],
+            events: vec![],
+        }
+    }
+
+    /// 录制鼠标轨迹: 距上一个点超过 sample 且移动超过 min_distance 像素时记录一个点
+    pub fn with_moves(mut self, sample: Duration, min_distance: f64) -> Self {
+        self.moves = Some((sample, min_distance));
+        self
+    }
+
+    /// 处理事件, 录制结束时返回 true
+    pub fn handle(&mut self, event: &Event) -> bool {
+        let (key, down) = match event.event_type {
+            EventType::KeyPress(key) => (Some(KeyOrButton::Key(key)), true),
+            EventType::KeyRelease(key) => (Some(KeyOrButton::Key(key)), false),
+            EventType::ButtonPress(button) => (Some(KeyOrButton::Mouse(button)), true),
+            EventType::ButtonRelease(button) => (Some(KeyOrButton::Mouse(button)), false),
+            _ => (None, false),
+        };
+        // 开始和结束的按键不录制
+        if let Some(key) = key.filter(|key| *key == self.start || *key == self.stop) {
+            if down && self.recording && key == self.stop {
+                self.end_path();
+                return true;
+            }
+            if down && !self.recording && key == self.start {
+                self.recording = true;
+                self.prev = Instant::now();
+                self.last = self.point;
+            }
+            return false;
+        }
+        if let EventType::MouseMove { x, y } = event.event_type {
+            self.point = (x, y);
+            if self.recording {
+                self.sample();
+            }
+            return false;
+        }
+        if !self.recording {
+            return false;
+        }
+
+        self.end_path();
+        self.last = self.point;
+        let curr = Instant::now();
+        let ms = curr.duration_since(self.prev).as_millis() as i64;
+        self.prev = curr;
+        if ms > 0 {
+            self.events.push(ScriptEvent::Sleep(ms.into()));
+        }
+
+        match event.event_type {
+            EventType::KeyPress(key) => self.events.push(ScriptEvent::KeyDown(key)),
+            EventType::KeyRelease(key) => self.events.push(ScriptEvent::KeyUp(key)),
+            EventType::ButtonPress(button) => {
+                let (x, y) = self.position();
+                self.events.push(ScriptEvent::Move(x, y, None));
+                self.events.push(ScriptEvent::ClickDown(button))
+            }
+            EventType::ButtonRelease(button) => {
+                let (x, y) = self.position();
+                self.events.push(ScriptEvent::Move(x, y, None));
+                self.events.push(ScriptEvent::ClickUp(button))
+            }
+            EventType::Wheel { delta_x, delta_y } => {
+                self.events.push(ScriptEvent::Scroll(delta_x.into(), delta_y.into()))
+            }
+            EventType::MouseMove { .. } => {}
+        }
+        false
+    }
+
+    /// 按采样间隔和最小距离记录轨迹点
+    fn sample(&mut self) {
+        let Some((sample, min_distance)) = self.moves else {
+            return;
+        };
+        let curr = Instant::now();
+        let elapsed = curr.duration_since(self.prev);
+        let distance = (self.point.0 - self.last.0).hypot(self.point.1 - self.last.1);
+        if elapsed < sample || distance < min_distance {
+            return;
+        }
+        self.prev = curr;
+        self.last = self.point;
+        let (x, y) = self.position();
+        self.path.push((elapsed.as_millis() as u64, x, y));
+    }
+
+    /// 结束录制中的轨迹
+    fn end_path(&mut self) {
+        if !self.path.is_empty() {
+            self.events.push(ScriptEvent::Path(mem::take(&mut self.path)));
+        }
+    }
+
+    /// 是否录制中
+    pub fn recording(&self) -> bool {
+        self.recording
+    }
+
+    /// 已录制的事件
+    pub fn events(&self) -> &[ScriptEvent] {
+        &self.events
+    }
+
+    /// 鼠标位置换算为配置中的位置, 与 [`Config::point`](crate::script::config::Config::point) 相反
+    fn position(&self) -> (Num, Num) {
+        let x = self.point.0 * self.scaling - self.offset.0;
+        let y = self.point.1 * self.scaling - self.offset.1;
+        ((x.round() as i64).into(), (y.round() as i64).into())
+    }
+}
+
+/// 输出录制的脚本; out 已存在时追加到末尾, 为 None 时输出到终端
+pub fn save(
+    out: Option<&Path>,
+    title: &str,
+    trigger: &KeyOrButton,
+    events: &[ScriptEvent],
+) -> Result<(), Box<dyn Error>> {
+    let text = script(title, trigger, events)?;
+    let Some(path) = out else {
+        print!("{text}");
+        return Ok(());
+    };
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() > 0 {
+        writeln!(file)?;
+    }
+    file.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// 生成 `[[scripts]]` 配置
+pub fn script(title: &str, trigger: &KeyOrButton, events: &[ScriptEvent]) -> Result<String, Box<dyn Error>> {
+    let mut s = String::from("[[scripts]]\n");
+    writeln!(s, "title = {}", toml::Value::try_from(title)?)?;
+    writeln!(s, "repeat = 1")?;
+    writeln!(s, "trigger = {}", toml::Value::try_from([trigger])?)?;
+    writeln!(s, "methods = {}", methods(events)?)?;
+    Ok(s)
+}
+
+/// 方法列表, 每行一个方法
+pub fn methods(events: &[ScriptEvent]) -> Result<String, Box<dyn Error>> {
+    let mut s = String::from("[\n");
+    for event in events {
+        writeln!(s, "    {},", method(event)?)?;
+    }
+    s.push(']');
+    Ok(s)
+}
+
+/// 单个方法的行内写法, 如 `{ event = "KeyDown", args = "KeyA" }`
+pub fn method(event: &ScriptEvent) -> Result<String, Box<dyn Error>> {
+    let toml::Value::Table(mut table) = toml::Value::try_from(event)? else {
+        return Err(format!("无法转换事件 {event:?}").into());
+    };
+    let name = table.remove("event").ok_or("缺少 event")?;
+    Ok(match table.remove("args") {
+        Some(args) => format!("{{ event = {name}, args = {args} }}"),
+        None => format!("{{ event = {name} }}"),
+    })
+}
+
+/// 优化配置文件中的脚本, 保留其余内容和注释; title 为 None 时优化所有脚本, out 为 None 时输出到终端
+pub fn optimize(
+    path: &Path,
+    out: Option<&Path>,
+    title: Option<&str>,
+    optimizer: &Optimizer,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::read(path)?;
+    if let Some(title) = title.filter(|title| config.scripts.iter().all(|item| item.title != *title)) {
+        return Err(format!("没有标题为 {title:?} 的脚本").into());
+    }
+
+    let mut doc: toml_edit::DocumentMut = fs::read_to_string(path)?.parse()?;
+    let tables = doc
+        .get_mut("scripts")
+        .and_then(|item| item.as_array_of_tables_mut())
+        .ok_or("scripts 需要写成 [[scripts]] 的形式")?;
+    for (table, item) in tables.iter_mut().zip(config.scripts) {
+        if title.is_some_and(|title| title != item.title) {
+            continue;
+        }
+        let events = optimizer.run(item.methods);
+        table["methods"] = toml_edit::value(methods(&events)?.parse::<toml_edit::Value>()?);
+    }
+
+    match out {
+        Some(out) => fs::write(out, doc.to_string())?,
+        None => print!("{doc}"),
+    }
+    Ok(())
+}
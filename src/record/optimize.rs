@@ -0,0 +1,560 @@
+use std::{collections::HashSet, mem};
+
+use clap::Args;
+
+use crate::script::config::{Delay, Num, ScriptEvent};
+
+/// 录制结果的优化参数
+#[derive(Debug, Clone, Copy, Args)]
+pub struct Optimizer {
+    /// 去掉小于该毫秒数的 Sleep
+    #[arg(long, default_value_t = 50)]
+    pub min_sleep: u64,
+    /// Sleep 按该毫秒数取整, 0 为不取整
+    #[arg(long, default_value_t = 10)]
+    pub sleep_grid: u64,
+    /// 简化鼠标轨迹时允许的误差(像素), 0 为不简化
+    #[arg(long, default_value_t = 2.0)]
+    pub epsilon: f64,
+}
+
+impl Optimizer {
+    /// 依次执行所有优化; 合并按键和点击时移出的 Sleep 最后再统一处理
+    pub fn run(&self, events: Vec<ScriptEvent>) -> Vec<ScriptEvent> {
+        let events = remove_repeats(events);
+        let events = self.paths(events);
+        let events = merge_keys(events);
+        let events = merge_clicks(events);
+        self.sleeps(events)
+    }
+
+    /// 合并连续的 Sleep, 去掉过短的并按 sleep_grid 取整
+    pub fn sleeps(&self, events: Vec<ScriptEvent>) -> Vec<ScriptEvent> {
+        let mut res = vec![];
+        let mut pending = 0.0;
+        for event in events {
+            if let ScriptEvent::Sleep(Delay::Fixed(n)) = &event {
+                if let Some(ms) = number(n) {
+                    pending += ms.max(0.0);
+                    continue;
+                }
+            }
+            self.push_sleep(&mut res, pending);
+            pending = 0.0;
+            res.push(event);
+        }
+        self.push_sleep(&mut res, pending);
+        res
+    }
+
+    fn push_sleep(&self, res: &mut Vec<ScriptEvent>, ms: f64) {
+        let ms = ms.round() as u64;
+        if ms < self.min_sleep {
+            return;
+        }
+        let ms = match self.sleep_grid {
+            0 => ms,
+            grid => (ms + grid / 2) / grid * grid,
+        };
+        if ms > 0 {
+            res.push(ScriptEvent::Sleep((ms as i64).into()));
+        }
+    }
+
+    /// 用 Ramer–Douglas–Peucker 算法简化连续的 Move 和 Path 的轨迹, 去掉的点的时间并入下一个点
+    pub fn paths(&self, events: Vec<ScriptEvent>) -> Vec<ScriptEvent> {
+        if self.epsilon <= 0.0 {
+            return events;
+        }
+        let mut res = vec![];
+        // 轨迹中的点及其之前的 Sleep
+        let mut path: Vec<(f64, (f64, f64))> = vec![];
+        let mut pending = 0.0;
+
+        for event in events {
+            let event = match event {
+                ScriptEvent::Path(points) => ScriptEvent::Path(self.simplify(points)),
+                event => event,
+            };
+            let point = match &event {
+                ScriptEvent::Move(x, y, None) => number(x).zip(number(y)),
+                _ => None,
+            };
+            if let Some(point) = point {
+                path.push((pending, point));
+                pending = 0.0;
+                continue;
+            }
+            if let ScriptEvent::Sleep(Delay::Fixed(n)) = &event {
+                if let Some(ms) = number(n).filter(|_| !path.is_empty()) {
+                    pending += ms;
+                    continue;
+                }
+            }
+            self.push_path(&mut res, &mut path, &mut pending);
+            res.push(event);
+        }
+        self.push_path(&mut res, &mut path, &mut pending);
+        res
+    }
+
+    /// 简化 Path 的轨迹点
+    fn simplify(&self, points: Vec<(u64, Num, Num)>) -> Vec<(u64, Num, Num)> {
+        let xy: Option<Vec<(f64, f64)>> = points.iter().map(|(_, x, y)| number(x).zip(number(y))).collect();
+        let Some(xy) = xy else {
+            return points;
+        };
+        let mut elapsed = 0;
+        points
+            .into_iter()
+            .zip(rdp(&xy, self.epsilon))
+            .filter_map(|((ms, x, y), keep)| {
+                elapsed += ms;
+                keep.then(|| (mem::take(&mut elapsed), x, y))
+            })
+            .collect()
+    }
+
+    fn push_path(&self, res: &mut Vec<ScriptEvent>, path: &mut Vec<(f64, (f64, f64))>, pending: &mut f64) {
+        let points: Vec<(f64, f64)> = path.iter().map(|(_, point)| *point).collect();
+        let keep = rdp(&points, self.epsilon);
+
+        let mut sleep = 0.0;
+        for ((ms, (x, y)), keep) in path.drain(..).zip(keep) {
+            sleep += ms;
+            if !keep {
+                continue;
+            }
+            if sleep > 0.0 {
+                res.push(ScriptEvent::Sleep((sleep.round() as i64).into()));
+                sleep = 0.0;
+            }
+            res.push(ScriptEvent::Move(num(x), num(y), None));
+        }
+        if *pending > 0.0 {
+            res.push(ScriptEvent::Sleep((pending.round() as i64).into()));
+        }
+        *pending = 0.0;
+    }
+}
+
+/// 去掉长按时系统重复发送的 KeyDown
+pub fn remove_repeats(events: Vec<ScriptEvent>) -> Vec<ScriptEvent> {
+    let mut held = HashSet::new();
+    events
+        .into_iter()
+        .filter(|event| match event {
+            ScriptEvent::KeyDown(key) => held.insert(*key),
+            ScriptEvent::KeyUp(key) => {
+                held.remove(key);
+                true
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+/// 连续按下又以任意顺序松开的按键合并为 Key 或 Keys, 其间的 Sleep 移到合并后的事件之前
+pub fn merge_keys(events: Vec<ScriptEvent>) -> Vec<ScriptEvent> {
+    merge(events, keys)
+}
+
+/// 移动后按下再松开的鼠标合并为 ClickOn 或 ClickTo, 原地按下再松开的合并为 Click, 其间的 Sleep 移到合并后的事件之前
+pub fn merge_clicks(events: Vec<ScriptEvent>) -> Vec<ScriptEvent> {
+    merge(events, click)
+}
+
+/// 合并后的事件, 合并掉的事件数和其间 Sleep 的毫秒数
+type Merged = Option<(ScriptEvent, usize, f64)>;
+
+/// 依次用 f 合并开头的事件
+fn merge(events: Vec<ScriptEvent>, f: fn(&[ScriptEvent]) -> Merged) -> Vec<ScriptEvent> {
+    let mut res = vec![];
+    let mut i = 0;
+    while i < events.len() {
+        let Some((event, len, sleep)) = f(&events[i..]) else {
+            res.push(events[i].clone());
+            i += 1;
+            continue;
+        };
+        if sleep > 0.0 {
+            res.push(ScriptEvent::Sleep((sleep.round() as i64).into()));
+        }
+        res.push(event);
+        i += len;
+    }
+    res
+}
+
+/// 开头连续按下又以任意顺序松开的按键
+fn keys(events: &[ScriptEvent]) -> Merged {
+    let (mut downs, mut ups, mut sleep) = (vec![], vec![], 0.0);
+    for (i, event) in events.iter().enumerate() {
+        if let Some(ms) = sleep_ms(event).filter(|_| !downs.is_empty()) {
+            sleep += ms;
+            continue;
+        }
+        match event {
+            ScriptEvent::KeyDown(key) if ups.is_empty() => downs.push(*key),
+            ScriptEvent::KeyUp(key) if downs.contains(key) && !ups.contains(key) => {
+                ups.push(*key);
+                if ups.len() == downs.len() {
+                    let event = match downs.len() {
+                        1 => ScriptEvent::Key(downs[0]),
+                        _ => ScriptEvent::Keys(downs),
+                    };
+                    return Some((event, i + 1, sleep));
+                }
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// 开头的鼠标按下再松开, 按下前紧挨着的 Move 为按下的位置, 松开前最多有一个 Move
+fn click(events: &[ScriptEvent]) -> Merged {
+    use ScriptEvent::{Click, ClickDown, ClickOn, ClickTo, ClickUp, Move};
+
+    let (start, skip) = match events {
+        [Move(x, y, None), ClickDown(_), ..] => (Some((x, y)), 1),
+        _ => (None, 0),
+    };
+    let ClickDown(button) = events.get(skip)? else {
+        return None;
+    };
+    let (mut end, mut sleep) = (None, 0.0);
+    for (i, event) in events.iter().enumerate().skip(skip + 1) {
+        if let Some(ms) = sleep_ms(event) {
+            sleep += ms;
+            continue;
+        }
+        match event {
+            Move(x, y, None) if end.is_none() => end = Some((x, y)),
+            ClickUp(b) if b == button => {
+                let event = match (start, end) {
+                    (Some((x, y)), Some((x2, y2))) if (x, y) != (x2, y2) => {
+                        ClickTo(*button, x.clone(), y.clone(), x2.clone(), y2.clone(), None)
+                    }
+                    (Some((x, y)), _) => ClickOn(*button, x.clone(), y.clone(), None),
+                    (None, None) => Click(*button),
+                    (None, Some(_)) => return None,
+                };
+                return Some((event, i + 1, sleep));
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Ramer–Douglas–Peucker 算法, 返回每个点是否保留
+fn rdp(points: &[(f64, f64)], epsilon: f64) -> Vec<bool> {
+    let mut keep = vec![false; points.len()];
+    let Some(last) = points.len().checked_sub(1) else {
+        return keep;
+    };
+    keep[0] = true;
+    keep[last] = true;
+
+    let mut stack = vec![(0, last)];
+    while let Some((start, end)) = stack.pop() {
+        let (mut index, mut max) = (start, 0.0);
+        for i in start + 1..end {
+            let d = distance(points[i], points[start], points[end]);
+            if d > max {
+                (index, max) = (i, d);
+            }
+        }
+        if max > epsilon {
+            keep[index] = true;
+            stack.push((start, index));
+            stack.push((index, end));
+        }
+    }
+    keep
+}
+
+/// 点 p 到直线 ab 的距离
+fn distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = dx.hypot(dy);
+    if len == 0.0 {
+        return (p.0 - a.0).hypot(p.1 - a.1);
+    }
+    ((p.0 - a.0) * dy - (p.1 - a.1) * dx).abs() / len
+}
+
+/// 固定 Sleep 的毫秒数
+fn sleep_ms(event: &ScriptEvent) -> Option<f64> {
+    match event {
+        ScriptEvent::Sleep(Delay::Fixed(n)) => number(n),
+        _ => None,
+    }
+}
+
+/// 数字参数的值, 表达式为 None
+fn number(n: &Num) -> Option<f64> {
+    match n {
+        Num::Int(n) => Some(*n as f64),
+        Num::Float(n) => Some(*n),
+        Num::Expr(_) => None,
+    }
+}
+
+fn num(n: f64) -> Num {
+    match n.fract() == 0.0 {
+        true => Num::Int(n as i64),
+        false => Num::Float(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdev::{Button, Key};
+
+    use super::*;
+
+    const OPTIMIZER: Optimizer = Optimizer { min_sleep: 50, sleep_grid: 10, epsilon: 2.0 };
+
+    fn mv(x: i64, y: i64) -> ScriptEvent {
+        ScriptEvent::Move(x.into(), y.into(), None)
+    }
+
+    fn sleep(ms: i64) -> ScriptEvent {
+        ScriptEvent::Sleep(ms.into())
+    }
+
+    #[test]
+    fn rdp_keeps_corners() {
+        assert!(rdp(&[], 1.0).is_empty());
+        assert_eq!(rdp(&[(1.0, 1.0)], 1.0), vec![true]);
+        let line = [(0.0, 0.0), (1.0, 0.5), (2.0, 0.0), (3.0, -0.5), (4.0, 0.0)];
+        assert_eq!(rdp(&line, 1.0), vec![true, false, false, false, true]);
+        // (2, 0) 正好在 (1, 0.5) 和 (3, -0.5) 的连线上
+        assert_eq!(rdp(&line, 0.1), vec![true, true, false, true, true]);
+        let corner = [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (10.0, 5.0), (10.0, 10.0)];
+        assert_eq!(rdp(&corner, 1.0), vec![true, false, true, false, true]);
+        assert_eq!(distance((3.0, 4.0), (0.0, 0.0), (0.0, 0.0)), 5.0);
+    }
+
+    #[test]
+    fn paths_merge_sleeps_of_removed_points() {
+        let events = vec![
+            mv(0, 0),
+            sleep(10),
+            mv(5, 0),
+            sleep(10),
+            mv(10, 1),
+            sleep(30),
+            ScriptEvent::Key(Key::KeyA),
+        ];
+        let expected = vec![mv(0, 0), sleep(20), mv(10, 1), sleep(30), ScriptEvent::Key(Key::KeyA)];
+        assert_eq!(OPTIMIZER.paths(events), expected);
+
+        let points = vec![
+            (0, 0.into(), 0.into()),
+            (16, 5.into(), 0.into()),
+            (16, 10.into(), 0.into()),
+        ];
+        let expected = vec![(0, 0.into(), 0.into()), (32, 10.into(), 0.into())];
+        assert_eq!(
+            OPTIMIZER.paths(vec![ScriptEvent::Path(points)]),
+            vec![ScriptEvent::Path(expected)]
+        );
+
+        // 含表达式的轨迹不简化
+        let points = vec![
+            (0, 0.into(), 0.into()),
+            (16, Num::Expr("${x}".into()), 0.into()),
+            (16, 10.into(), 0.into()),
+        ];
+        assert_eq!(
+            OPTIMIZER.paths(vec![ScriptEvent::Path(points.clone())]),
+            vec![ScriptEvent::Path(points)]
+        );
+    }
+
+    #[test]
+    fn sleeps_are_merged_dropped_and_rounded() {
+        let events = vec![
+            sleep(20),
+            sleep(24),
+            ScriptEvent::Key(Key::KeyA),
+            sleep(30),
+            sleep(34),
+            sleep(10),
+        ];
+        assert_eq!(OPTIMIZER.sleeps(events), vec![ScriptEvent::Key(Key::KeyA), sleep(70)]);
+        assert_eq!(OPTIMIZER.sleeps(vec![sleep(64)]), vec![sleep(60)]);
+        let raw = Optimizer { min_sleep: 0, sleep_grid: 0, epsilon: 0.0 };
+        assert_eq!(raw.sleeps(vec![sleep(3), sleep(4)]), vec![sleep(7)]);
+    }
+
+    #[test]
+    fn keys_are_merged() {
+        use ScriptEvent::{KeyDown, KeyUp};
+
+        let events = vec![
+            KeyDown(Key::KeyA),
+            KeyDown(Key::KeyA),
+            KeyDown(Key::KeyA),
+            KeyUp(Key::KeyA),
+        ];
+        assert_eq!(merge_keys(remove_repeats(events)), vec![ScriptEvent::Key(Key::KeyA)]);
+
+        let events = vec![
+            KeyDown(Key::ControlLeft),
+            KeyDown(Key::KeyC),
+            KeyUp(Key::ControlLeft),
+            KeyUp(Key::KeyC),
+        ];
+        assert_eq!(
+            merge_keys(events),
+            vec![ScriptEvent::Keys(vec![Key::ControlLeft, Key::KeyC])]
+        );
+
+        // 按住的时间移到合并后的事件之前
+        let events = vec![
+            KeyDown(Key::ShiftLeft),
+            sleep(40),
+            KeyDown(Key::KeyA),
+            sleep(100),
+            KeyUp(Key::KeyA),
+            KeyUp(Key::ShiftLeft),
+            sleep(30),
+        ];
+        let expected = vec![
+            sleep(140),
+            ScriptEvent::Keys(vec![Key::ShiftLeft, Key::KeyA]),
+            sleep(30),
+        ];
+        assert_eq!(merge_keys(events), expected);
+
+        // 中间有其他事件时保留按下和松开
+        let events = vec![KeyDown(Key::KeyA), sleep(100), mv(1, 1), KeyUp(Key::KeyA)];
+        assert_eq!(merge_keys(events.clone()), events);
+    }
+
+    #[test]
+    fn clicks_are_merged() {
+        use ScriptEvent::{Click, ClickDown, ClickOn, ClickTo, ClickUp};
+
+        let events = vec![mv(1, 2), ClickDown(Button::Left), mv(1, 2), ClickUp(Button::Left)];
+        assert_eq!(
+            merge_clicks(events),
+            vec![ClickOn(Button::Left, 1.into(), 2.into(), None)]
+        );
+
+        let events = vec![mv(1, 2), ClickDown(Button::Left), mv(3, 4), ClickUp(Button::Left)];
+        let expected = ClickTo(Button::Left, 1.into(), 2.into(), 3.into(), 4.into(), None);
+        assert_eq!(merge_clicks(events), vec![expected]);
+
+        let events = vec![
+            ClickDown(Button::Right),
+            ClickUp(Button::Right),
+            ClickDown(Button::Left),
+        ];
+        assert_eq!(
+            merge_clicks(events),
+            vec![Click(Button::Right), ClickDown(Button::Left)]
+        );
+
+        let events = vec![ClickDown(Button::Left), ClickUp(Button::Right)];
+        assert_eq!(merge_clicks(events.clone()), events);
+
+        let events = vec![
+            mv(1, 2),
+            ClickDown(Button::Left),
+            sleep(60),
+            mv(3, 4),
+            sleep(40),
+            ClickUp(Button::Left),
+        ];
+        let expected = ClickTo(Button::Left, 1.into(), 2.into(), 3.into(), 4.into(), None);
+        assert_eq!(merge_clicks(events), vec![sleep(100), expected]);
+
+        // 按住时拖动了多段的保留按下和松开
+        let events = vec![
+            mv(1, 2),
+            ClickDown(Button::Left),
+            sleep(60),
+            mv(3, 4),
+            sleep(40),
+            mv(5, 6),
+            ClickUp(Button::Left),
+        ];
+        assert_eq!(merge_clicks(events.clone()), events);
+    }
+
+    #[test]
+    fn run_all_passes() {
+        use ScriptEvent::{ClickDown, ClickOn, ClickUp, KeyDown, KeyUp};
+
+        let events = vec![
+            mv(0, 0),
+            sleep(8),
+            mv(50, 0),
+            sleep(8),
+            mv(100, 0),
+            ClickDown(Button::Left),
+            sleep(100),
+            ClickUp(Button::Left),
+            sleep(300),
+            KeyDown(Key::KeyA),
+            sleep(30),
+            KeyDown(Key::KeyA),
+            sleep(94),
+            KeyUp(Key::KeyA),
+        ];
+        let expected = vec![
+            mv(0, 0),
+            sleep(120),
+            ClickOn(Button::Left, 100.into(), 0.into(), None),
+            sleep(420),
+            ScriptEvent::Key(Key::KeyA),
+        ];
+        assert_eq!(OPTIMIZER.run(events), expected);
+    }
+
+    #[test]
+    fn run_recorded_stream() {
+        use ScriptEvent::{ClickDown, ClickTo, ClickUp, KeyDown, KeyUp, Path};
+
+        // 与 Recorder 输出的形式相同: 轨迹, Sleep, 按下前和松开前各一个 Move
+        let path = vec![
+            (0, 0.into(), 0.into()),
+            (16, 50.into(), 0.into()),
+            (16, 100.into(), 0.into()),
+        ];
+        let events = vec![
+            Path(path),
+            sleep(200),
+            mv(100, 0),
+            ClickDown(Button::Left),
+            sleep(100),
+            mv(100, 0),
+            ClickUp(Button::Left),
+            sleep(500),
+            mv(100, 0),
+            ClickDown(Button::Left),
+            sleep(100),
+            mv(140, 30),
+            ClickUp(Button::Left),
+            sleep(250),
+            KeyDown(Key::KeyA),
+            sleep(103),
+            KeyUp(Key::KeyA),
+        ];
+        let expected = vec![
+            Path(vec![(0, 0.into(), 0.into()), (32, 100.into(), 0.into())]),
+            sleep(300),
+            ScriptEvent::ClickOn(Button::Left, 100.into(), 0.into(), None),
+            sleep(600),
+            ClickTo(Button::Left, 100.into(), 0.into(), 140.into(), 30.into(), None),
+            sleep(350),
+            ScriptEvent::Key(Key::KeyA),
+        ];
+        assert_eq!(OPTIMIZER.run(events), expected);
+    }
+}
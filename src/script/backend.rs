@@ -0,0 +1,180 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    time::Instant,
+};
+
+use rdev::{listen, simulate, Event, EventType, ListenError, SimulateError};
+
+use crate::script::trigger::Swallow;
+
+/// 键鼠输入后端
+pub trait InputBackend: Send + Sync {
+    /// 模拟事件
+    fn simulate(&self, event_type: &EventType) -> Result<(), SimulateError>;
+
+    /// 监听事件(阻塞直到监听结束)
+    fn listen(&self, callback: Box<dyn FnMut(Event) + Send>) -> Result<(), ListenError>;
+
+    /// 屏蔽按键, 使其仍能被监听到但不再传给当前窗口; 每次调用替换之前屏蔽的按键
+    fn swallow(&self, keys: &HashSet<Swallow>) -> Result<(), String>;
+
+    /// 输入键盘上没有的字符; 不支持时返回 None, 改为通过剪贴板粘贴
+    fn unicode(&self, text: &str) -> Option<Result<(), String>>;
+}
+
+/// 真实设备, 通过 rdev 模拟和监听
+#[derive(Debug, Default)]
+pub struct RdevBackend {
+    /// 输入字符时复用的连接
+    #[cfg(target_os = "linux")]
+    display: crate::script::xconn::XConnection,
+}
+
+impl InputBackend for RdevBackend {
+    #[cfg(target_os = "linux")]
+    fn simulate(&self, event_type: &EventType) -> Result<(), SimulateError> {
+        match crate::script::grab::press(event_type) {
+            Some(res) => res.map_err(|_| SimulateError),
+            None => simulate(event_type),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn simulate(&self, event_type: &EventType) -> Result<(), SimulateError> {
+        simulate(event_type)
+    }
+
+    fn listen(&self, callback: Box<dyn FnMut(Event) + Send>) -> Result<(), ListenError> {
+        listen(callback)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn swallow(&self, keys: &HashSet<Swallow>) -> Result<(), String> {
+        crate::script::grab::swallow(keys)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn swallow(&self, keys: &HashSet<Swallow>) -> Result<(), String> {
+        match keys.is_empty() {
+            true => Ok(()),
+            false => Err("当前平台暂不支持屏蔽按键".into()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn unicode(&self, text: &str) -> Option<Result<(), String>> {
+        Some(
+            self.display
+                .with(|display| unsafe { crate::script::typing::unicode(display, text) }),
+        )
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn unicode(&self, _text: &str) -> Option<Result<(), String>> {
+        None
+    }
+}
+
+/// 内存中的假设备: 记录所有模拟的事件, 监听的事件由 [`RecordBackend::new`] 返回的 Sender 输入
+#[derive(Debug)]
+pub struct RecordBackend {
+    events: Mutex<Vec<(Instant, EventType)>>,
+    input: Mutex<Option<mpsc::Receiver<Event>>>,
+    received: AtomicUsize,
+    swallowed: Mutex<HashSet<Swallow>>,
+    typed: Mutex<String>,
+    unicode: bool,
+}
+
+impl RecordBackend {
+    pub fn new() -> (Self, mpsc::Sender<Event>) {
+        let (tx, rx) = mpsc::channel();
+        let backend = Self {
+            events: Mutex::new(vec![]),
+            input: Mutex::new(Some(rx)),
+            received: AtomicUsize::new(0),
+            swallowed: Default::default(),
+            typed: Default::default(),
+            unicode: true,
+        };
+        (backend, tx)
+    }
+
+    /// 是否支持输入键盘上没有的字符, 不支持时脚本通过剪贴板粘贴
+    pub fn with_unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    /// 已模拟的事件及其时间, 时间取自 tokio 的时钟, 暂停时钟时为虚拟的时间
+    pub fn events(&self) -> Vec<(Instant, EventType)> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// 已模拟的事件
+    pub fn event_types(&self) -> Vec<EventType> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, event_type)| *event_type)
+            .collect()
+    }
+
+    /// 已回调的输入事件数
+    pub fn received(&self) -> usize {
+        self.received.load(Ordering::SeqCst)
+    }
+
+    /// 当前屏蔽的按键
+    pub fn swallowed(&self) -> HashSet<Swallow> {
+        self.swallowed.lock().unwrap().clone()
+    }
+
+    /// 通过 unicode 输入的字符
+    pub fn typed(&self) -> String {
+        self.typed.lock().unwrap().clone()
+    }
+
+    /// 清空已记录的事件
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear()
+    }
+}
+
+impl InputBackend for RecordBackend {
+    fn simulate(&self, event_type: &EventType) -> Result<(), SimulateError> {
+        let now = tokio::time::Instant::now().into_std();
+        self.events.lock().unwrap().push((now, *event_type));
+        Ok(())
+    }
+
+    /// 依次回调输入的事件, Sender 全部关闭后返回; 只能监听一次
+    fn listen(&self, mut callback: Box<dyn FnMut(Event) + Send>) -> Result<(), ListenError> {
+        let Some(rx) = self.input.lock().unwrap().take() else {
+            return Ok(());
+        };
+        for event in rx {
+            callback(event);
+            self.received.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    fn swallow(&self, keys: &HashSet<Swallow>) -> Result<(), String> {
+        *self.swallowed.lock().unwrap() = keys.clone();
+        Ok(())
+    }
+
+    fn unicode(&self, text: &str) -> Option<Result<(), String>> {
+        if !self.unicode {
+            return None;
+        }
+        self.typed.lock().unwrap().push_str(text);
+        Some(Ok(()))
+    }
+}
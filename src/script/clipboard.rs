@@ -0,0 +1,398 @@
+use std::sync::Mutex;
+
+use rdev::Key;
+
+/// 粘贴快捷键的修饰键, 与 V 组合
+#[cfg(not(target_os = "macos"))]
+pub const PASTE: Key = Key::ControlLeft;
+
+/// 粘贴快捷键的修饰键, 与 V 组合
+#[cfg(target_os = "macos")]
+pub const PASTE: Key = Key::MetaLeft;
+
+/// 剪贴板
+pub trait Clipboard: Send + Sync {
+    /// 读取文本, 为空或不是文本时为空字符串
+    fn get(&self) -> Result<String, String>;
+
+    /// 写入文本
+    fn set(&self, text: &str) -> Result<(), String>;
+}
+
+/// 内存中的剪贴板
+#[derive(Debug, Default)]
+pub struct MemoryClipboard(Mutex<String>);
+
+impl MemoryClipboard {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self(Mutex::new(text.into()))
+    }
+}
+
+impl Clipboard for MemoryClipboard {
+    fn get(&self) -> Result<String, String> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    fn set(&self, text: &str) -> Result<(), String> {
+        *self.0.lock().unwrap() = text.to_string();
+        Ok(())
+    }
+}
+
+/// X11 的 CLIPBOARD 选区
+///
+/// 写入后由后台线程持有选区并响应其他程序的读取, 直到其他程序写入新的内容
+#[cfg(target_os = "linux")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct X11Clipboard;
+
+#[cfg(target_os = "linux")]
+impl Clipboard for X11Clipboard {
+    fn get(&self) -> Result<String, String> {
+        selection::request(selection::Request::Get)
+    }
+
+    fn set(&self, text: &str) -> Result<(), String> {
+        selection::request(|reply| selection::Request::Set(text.to_string(), reply))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod selection {
+    use std::{
+        mem,
+        os::raw::{c_int, c_long, c_uchar},
+        ptr,
+        sync::{
+            mpsc::{self, Receiver, Sender, TryRecvError},
+            OnceLock,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use x11::xlib;
+
+    use crate::script::xconn;
+
+    /// 读取其他程序的剪贴板的超时时间
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub enum Request {
+        Get(Sender<Result<String, String>>),
+        Set(String, Sender<Result<(), String>>),
+    }
+
+    static SELECTION: OnceLock<Sender<Request>> = OnceLock::new();
+
+    /// 发送请求到后台线程并等待结果
+    pub fn request<T>(request: impl FnOnce(Sender<Result<T, String>>) -> Request) -> Result<T, String> {
+        let selection = SELECTION.get_or_init(|| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || run(rx));
+            tx
+        });
+
+        let closed = || "剪贴板的线程已退出".to_string();
+        let (tx, rx) = mpsc::channel();
+        selection.send(request(tx)).map_err(|_| closed())?;
+        rx.recv().map_err(|_| closed())?
+    }
+
+    struct Atoms {
+        clipboard: xlib::Atom,
+        utf8: xlib::Atom,
+        targets: xlib::Atom,
+        /// 读取时存放结果的属性
+        property: xlib::Atom,
+    }
+
+    /// 持有 X11 连接和选区, 处理读写请求和其他程序的读取
+    fn run(rx: Receiver<Request>) {
+        unsafe {
+            // 请求读取的程序可能已经关闭, 连接上的 BadWindow 错误只做记录
+            let display = match xconn::open() {
+                Ok(display) => display,
+                Err(err) => {
+                    for request in rx {
+                        let err = err.clone();
+                        match request {
+                            Request::Get(reply) => {
+                                let _ = reply.send(Err(err));
+                            }
+                            Request::Set(_, reply) => {
+                                let _ = reply.send(Err(err));
+                            }
+                        }
+                    }
+                    return;
+                }
+            };
+            let root = xlib::XDefaultRootWindow(display);
+            let window = xlib::XCreateSimpleWindow(display, root, 0, 0, 1, 1, 0, 0, 0);
+            let atom = |name: &std::ffi::CStr| xlib::XInternAtom(display, name.as_ptr(), xlib::False);
+            let atoms = Atoms {
+                clipboard: atom(c"CLIPBOARD"),
+                utf8: atom(c"UTF8_STRING"),
+                targets: atom(c"TARGETS"),
+                property: atom(c"KMM_CLIPBOARD"),
+            };
+            let mut fd = libc::pollfd {
+                fd: xlib::XConnectionNumber(display),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // 当前持有的内容, 被其他程序替换后为 None
+            let mut owned: Option<String> = None;
+            // 等待其他程序回复的读取请求
+            let mut pending: Option<(Sender<Result<String, String>>, Instant)> = None;
+
+            loop {
+                while xlib::XPending(display) > 0 {
+                    let mut event: xlib::XEvent = mem::zeroed();
+                    xlib::XNextEvent(display, &mut event);
+                    match event.get_type() {
+                        xlib::SelectionRequest => respond(display, &atoms, &event.selection_request, owned.as_deref()),
+                        xlib::SelectionClear => owned = None,
+                        xlib::SelectionNotify => {
+                            if let Some((reply, _)) = pending.take() {
+                                let _ = reply.send(read(display, window, &atoms, &event.selection));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if pending
+                    .as_ref()
+                    .is_some_and(|(_, deadline)| Instant::now() >= *deadline)
+                {
+                    let (reply, _) = pending.take().unwrap();
+                    let _ = reply.send(Err("读取剪贴板超时".into()));
+                }
+
+                match rx.try_recv() {
+                    Ok(Request::Set(text, reply)) => {
+                        xlib::XSetSelectionOwner(display, atoms.clipboard, window, xlib::CurrentTime);
+                        let res = match xlib::XGetSelectionOwner(display, atoms.clipboard) == window {
+                            true => Ok(()),
+                            false => Err("无法写入剪贴板".into()),
+                        };
+                        owned = res.is_ok().then_some(text);
+                        let _ = reply.send(res);
+                    }
+                    Ok(Request::Get(reply)) => match (&owned, xlib::XGetSelectionOwner(display, atoms.clipboard)) {
+                        (Some(text), _) => {
+                            let _ = reply.send(Ok(text.clone()));
+                        }
+                        (None, 0) => {
+                            let _ = reply.send(Ok(String::new()));
+                        }
+                        (None, _) => {
+                            xlib::XConvertSelection(
+                                display,
+                                atoms.clipboard,
+                                atoms.utf8,
+                                atoms.property,
+                                window,
+                                xlib::CurrentTime,
+                            );
+                            xlib::XFlush(display);
+                            if let Some((prev, _)) = pending.replace((reply, Instant::now() + TIMEOUT)) {
+                                let _ = prev.send(Err("读取剪贴板被新的读取取代".into()));
+                            }
+                        }
+                    },
+                    Err(TryRecvError::Empty) => {
+                        libc::poll(&mut fd, 1, 50);
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            }
+            xlib::XDestroyWindow(display, window);
+            xconn::close(display);
+        }
+    }
+
+    /// 回复其他程序的读取
+    unsafe fn respond(
+        display: *mut xlib::Display,
+        atoms: &Atoms,
+        request: &xlib::XSelectionRequestEvent,
+        owned: Option<&str>,
+    ) {
+        // 旧的程序可能不指定属性
+        let property = match request.property {
+            0 => request.target,
+            property => property,
+        };
+        let property = match (owned, request.target) {
+            (Some(_), target) if target == atoms.targets => {
+                let targets = [atoms.targets, atoms.utf8, xlib::XA_STRING];
+                change(
+                    display,
+                    request.requestor,
+                    property,
+                    xlib::XA_ATOM,
+                    32,
+                    targets.as_ptr().cast(),
+                    3,
+                );
+                property
+            }
+            (Some(text), target) if target == atoms.utf8 => {
+                let (ptr, len) = (text.as_ptr(), text.len() as c_int);
+                change(display, request.requestor, property, target, 8, ptr, len);
+                property
+            }
+            (Some(text), xlib::XA_STRING) => {
+                let text = latin1(text);
+                let (ptr, len) = (text.as_ptr(), text.len() as c_int);
+                change(display, request.requestor, property, xlib::XA_STRING, 8, ptr, len);
+                property
+            }
+            _ => 0,
+        };
+
+        let mut notify: xlib::XEvent = mem::zeroed();
+        notify.selection = xlib::XSelectionEvent {
+            type_: xlib::SelectionNotify,
+            serial: 0,
+            send_event: xlib::True,
+            display,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property,
+            time: request.time,
+        };
+        xlib::XSendEvent(display, request.requestor, xlib::False, 0, &mut notify);
+        xlib::XFlush(display);
+    }
+
+    /// STRING 类型为 Latin-1 编码, 超出范围的字符替换为 ?
+    pub fn latin1(text: &str) -> Vec<u8> {
+        text.chars().map(|ch| u8::try_from(ch).unwrap_or(b'?')).collect()
+    }
+
+    unsafe fn change(
+        display: *mut xlib::Display,
+        window: xlib::Window,
+        property: xlib::Atom,
+        ty: xlib::Atom,
+        format: c_int,
+        data: *const c_uchar,
+        len: c_int,
+    ) {
+        xlib::XChangeProperty(display, window, property, ty, format, xlib::PropModeReplace, data, len);
+    }
+
+    /// 读取其他程序回复的内容
+    unsafe fn read(
+        display: *mut xlib::Display,
+        window: xlib::Window,
+        atoms: &Atoms,
+        event: &xlib::XSelectionEvent,
+    ) -> Result<String, String> {
+        if event.property == 0 {
+            return Ok(String::new());
+        }
+        let (mut actual, mut format, mut len, mut remain) = (0, 0, 0, 0);
+        let mut data = ptr::null_mut();
+        xlib::XGetWindowProperty(
+            display,
+            window,
+            atoms.property,
+            0,
+            c_long::MAX / 4,
+            xlib::True,
+            xlib::AnyPropertyType as xlib::Atom,
+            &mut actual,
+            &mut format,
+            &mut len,
+            &mut remain,
+            &mut data,
+        );
+        if data.is_null() {
+            return Ok(String::new());
+        }
+        let text = match format {
+            8 => Ok(String::from_utf8_lossy(std::slice::from_raw_parts(data, len as usize)).into_owned()),
+            _ => Err("剪贴板内容过大或不是文本".to_string()),
+        };
+        xlib::XFree(data.cast());
+        text
+    }
+}
+
+/// Windows 的剪贴板, 每次读写时打开
+#[cfg(target_os = "windows")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WindowsClipboard;
+
+#[cfg(target_os = "windows")]
+impl Clipboard for WindowsClipboard {
+    fn get(&self) -> Result<String, String> {
+        let mut clipboard = arboard::Clipboard::new().map_err(|err| err.to_string())?;
+        match clipboard.get_text() {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn set(&self, text: &str) -> Result<(), String> {
+        let mut clipboard = arboard::Clipboard::new().map_err(|err| err.to_string())?;
+        clipboard.set_text(text).map_err(|err| err.to_string())
+    }
+}
+
+/// 不支持剪贴板的平台
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UnsupportedClipboard;
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+impl Clipboard for UnsupportedClipboard {
+    fn get(&self) -> Result<String, String> {
+        Err("当前平台暂不支持剪贴板".into())
+    }
+
+    fn set(&self, _: &str) -> Result<(), String> {
+        Err("当前平台暂不支持剪贴板".into())
+    }
+}
+
+/// 当前平台的剪贴板
+#[cfg(target_os = "linux")]
+pub type DefaultClipboard = X11Clipboard;
+
+/// 当前平台的剪贴板
+#[cfg(target_os = "windows")]
+pub type DefaultClipboard = WindowsClipboard;
+
+/// 当前平台的剪贴板
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+pub type DefaultClipboard = UnsupportedClipboard;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_clipboard() {
+        let clipboard = MemoryClipboard::new("old");
+        assert_eq!(clipboard.get(), Ok("old".to_string()));
+        clipboard.set("世界").unwrap();
+        assert_eq!(clipboard.get(), Ok("世界".to_string()));
+        assert_eq!(MemoryClipboard::default().get(), Ok(String::new()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn string_target_is_latin1() {
+        assert_eq!(selection::latin1("abc"), b"abc");
+        assert_eq!(selection::latin1("café"), [b'c', b'a', b'f', 0xe9]);
+        assert_eq!(selection::latin1("世界!"), b"??!");
+    }
+}
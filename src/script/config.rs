@@ -2,17 +2,32 @@ use std::{
     collections::{HashMap, HashSet},
     error::Error,
     fs, mem,
-    path::Path,
-    process::exit,
-    sync::Arc,
+    path::{Path, PathBuf},
+    process::{exit, Stdio},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
 use rdev::{Button, EventType, Key};
 use serde::{Deserialize, Serialize};
-use tokio::time::sleep;
+use tokio::{
+    process::Command,
+    sync::mpsc::UnboundedSender,
+    task::spawn_blocking,
+    time::{self, sleep},
+};
 
-use crate::script::{window::WindowList, Script, ScriptList};
+use crate::script::{
+    clipboard::PASTE,
+    expr::{Expr, Op, Vars},
+    focus::WindowInfo,
+    motion::{Curve, Movement},
+    random::Random,
+    screen::{Color, Frame, Pixel, Template},
+    trigger::{Matcher, Trigger},
+    window::{Notice, WindowList},
+    Context, Control, Script, ScriptList, Status,
+};
 
 /// 脚本配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,14 +46,44 @@ pub struct Config {
     pub font_color: (u8, u8, u8),
     /// 是否显示边框
     pub border: bool,
+    /// 变量初始值
+    #[serde(default)]
+    pub vars: Vars,
+    /// 按焦点窗口切换的方案
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    /// 初始的层, 最后一个位于最上方
+    #[serde(default)]
+    pub layers: Vec<String>,
+    /// 随机数种子, 相同的种子每次运行的随机延迟和偏移都相同; 为空时每次不同
+    pub seed: Option<u64>,
+    /// Sleep 和延迟随机增减的毫秒数
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// 鼠标移动的位置随机偏移的像素数
+    #[serde(default)]
+    pub jitter_px: f64,
     pub blocks: HashMap<String, Vec<ScriptEvent>>,
     pub scripts: Vec<ScriptItem>,
+    /// 配置文件所在的目录, 图片等相对路径以此为准
+    #[serde(skip)]
+    pub dir: PathBuf,
 }
 
 impl Config {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<(ScriptList, WindowList), Box<dyn Error>> {
+        let config = Self::read(path)?;
+        let win = WindowList::init(config.point, config.font_size, config.font_color, config.border);
+        let list = config.build(&win.updater, &win.notifier)?;
+        Ok((list, win))
+    }
+
+    /// 读取并校验配置
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
         let data = fs::read_to_string(path)?;
         let mut config: Self = toml::from_str(&data)?;
+        config.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
 
         let hs: HashSet<&String> = config.scripts.iter().map(|m| &m.title).collect();
 
@@ -46,33 +91,156 @@ impl Config {
             return Err("title 不可重复".into());
         }
 
-        let win = WindowList::init(config.point, config.font_size, config.font_color, config.border);
+        let profiles: HashSet<&String> = config.profiles.iter().map(|p| &p.name).collect();
+        if config.profiles.len() != profiles.len() {
+            return Err("profiles 的 name 不可重复".into());
+        }
+        for item in config.scripts.iter() {
+            if let Some(name) = item.profiles.iter().find(|name| !profiles.contains(name)) {
+                return Err(format!("脚本 {:?} 的方案 {name:?} 不存在", item.title).into());
+            }
+        }
+
+        Ok(config)
+    }
 
+    /// 生成脚本列表
+    pub fn build(
+        mut self,
+        updater: &UnboundedSender<Status>,
+        notifier: &UnboundedSender<Notice>,
+    ) -> Result<ScriptList, Box<dyn Error>> {
         let mut scripts = vec![];
-        mem::swap(&mut config.scripts, &mut scripts);
+        mem::swap(&mut self.scripts, &mut scripts);
 
         let list: Result<Vec<Script>, Box<dyn Error>> = scripts
             .into_iter()
             .map(|item| {
+                let trigger = Matcher::new(item.trigger()?);
+                let title = Arc::new(item.title);
                 Ok(Script {
-                    title: Arc::new(item.title),
-                    delay: item.delay.unwrap_or(config.delay),
-                    trigger: item.trigger.into_iter().map(|m| (m, false)).collect(),
+                    status: Arc::new(Mutex::new(Status::new(title.clone()))),
+                    title,
+                    delay: item.delay.unwrap_or(self.delay),
+                    jitter_ms: item.jitter_ms.unwrap_or(self.jitter_ms),
+                    jitter_px: item.jitter_px.unwrap_or(self.jitter_px),
+                    trigger,
+                    profiles: item.profiles,
+                    layer: item.layer,
                     repeat: item.repeat,
+                    mode: item.mode,
+                    swallow: item.swallow,
                     task: None,
-                    methods: Arc::new(config.transform(item.methods)?),
-                    updater: win.updater.clone(),
+                    methods: Arc::new(self.transform(item.methods)?),
+                    vars: Arc::new(self.vars.clone()),
+                    updater: updater.clone(),
+                    notifier: notifier.clone(),
                 })
             })
             .collect();
 
-        Ok((ScriptList(list?), win))
+        Ok(ScriptList {
+            scripts: list?,
+            profiles: self.profiles.clone(),
+            layers: self.layers.clone(),
+            seed: self.seed,
+            profile: None,
+            focus_error: false,
+            swallowed: HashSet::new(),
+            notifier: notifier.clone(),
+        })
+    }
+
+    /// 监听配置文件的修改, 重新加载脚本
+    pub async fn watch(
+        path: PathBuf,
+        control: UnboundedSender<Control>,
+        updater: UnboundedSender<Status>,
+        notifier: UnboundedSender<Notice>,
+    ) {
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut prev = modified(&path);
+
+        loop {
+            sleep(Duration::from_millis(500)).await;
+            let curr = modified(&path);
+            if curr.is_none() || curr == prev {
+                continue;
+            }
+            prev = curr;
+
+            if Self::reload(&path, &control, &updater, &notifier).is_err() && control.is_closed() {
+                return;
+            }
+        }
+    }
+
+    /// 重新加载配置替换脚本列表, 并在窗口提示结果
+    pub fn reload(
+        path: &Path,
+        control: &UnboundedSender<Control>,
+        updater: &UnboundedSender<Status>,
+        notifier: &UnboundedSender<Notice>,
+    ) -> Result<(), String> {
+        let res = match Self::read(path).and_then(|config| config.build(updater, notifier)) {
+            Ok(list) => control
+                .send(Control::Reload(list))
+                .map_err(|_| "脚本列表已停止".to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        let message = match &res {
+            Ok(()) => "配置已重载".to_string(),
+            Err(err) => format!("配置重载失败: {err}"),
+        };
+        let _ = notifier.send(Notice::Message(message));
+        res
+    }
+
+    /// 按缩放和偏移换算屏幕位置
+    pub fn point(&self, x: &Num, y: &Num) -> Result<(Expr, Expr), Box<dyn Error>> {
+        let x = Expr::binary(Op::Add, x.compile()?, Expr::Num(self.offset.0));
+        let y = Expr::binary(Op::Add, y.compile()?, Expr::Num(self.offset.1));
+        let scaling = Expr::Num(self.scaling);
+        Ok((
+            Expr::binary(Op::Div, x, scaling.clone()),
+            Expr::binary(Op::Div, y, scaling),
+        ))
     }
 
-    pub fn mouse_move(&self, x: f64, y: f64) -> EventType {
-        let x = (x + self.offset.0) / self.scaling;
-        let y = (y + self.offset.1) / self.scaling;
-        EventType::MouseMove { x, y }
+    pub fn mouse_move(&self, x: &Num, y: &Num, motion: Option<&Motion>) -> Result<Method, Box<dyn Error>> {
+        let (x, y) = self.point(x, y)?;
+        let motion = motion.map(|motion| motion.compile(self.scaling)).transpose()?;
+        Ok(Method::Move(x, y, motion))
+    }
+
+    pub fn pixel(&self, x: &Num, y: &Num, color: Color, tolerance: u8) -> Result<Pixel, Box<dyn Error>> {
+        let (x, y) = self.point(x, y)?;
+        Ok(Pixel { x, y, color, tolerance })
+    }
+
+    pub fn template(
+        &self,
+        path: &Path,
+        threshold: f64,
+        region: Option<(Num, Num, Num, Num)>,
+    ) -> Result<Template, Box<dyn Error>> {
+        let region = match region {
+            Some((x, y, width, height)) => {
+                let (x, y) = self.point(&x, &y)?;
+                let scaling = Expr::Num(self.scaling);
+                let width = Expr::binary(Op::Div, width.compile()?, scaling.clone());
+                let height = Expr::binary(Op::Div, height.compile()?, scaling);
+                Some([x, y, width, height])
+            }
+            None => None,
+        };
+        Ok(Template {
+            image: Arc::new(Frame::open(self.dir.join(path))?),
+            threshold,
+            region,
+            scaling: self.scaling,
+            offset: self.offset,
+        })
     }
 
     pub fn transform(&self, methods: Vec<ScriptEvent>) -> Result<Vec<Method>, Box<dyn Error>> {
@@ -85,15 +253,15 @@ impl Config {
                     res.push(Method::mouse_down(button));
                     res.push(Method::mouse_up(button));
                 }
-                ScriptEvent::ClickOn(button, x, y) => {
-                    res.push(Method::Event(self.mouse_move(x, y)));
+                ScriptEvent::ClickOn(button, x, y, motion) => {
+                    res.push(self.mouse_move(&x, &y, motion.as_ref())?);
                     res.push(Method::mouse_down(button));
                     res.push(Method::mouse_up(button));
                 }
-                ScriptEvent::ClickTo(button, x, y, x2, y2) => {
-                    res.push(Method::Event(self.mouse_move(x, y)));
+                ScriptEvent::ClickTo(button, x, y, x2, y2, motion) => {
+                    res.push(self.mouse_move(&x, &y, motion.as_ref())?);
                     res.push(Method::mouse_down(button));
-                    res.push(Method::Event(self.mouse_move(x2, y2)));
+                    res.push(self.mouse_move(&x2, &y2, motion.as_ref())?);
                     res.push(Method::mouse_up(button));
                 }
                 ScriptEvent::KeyDown(key) => res.push(Method::key_down(key)),
@@ -106,10 +274,85 @@ impl Config {
                     keys.iter().for_each(|key| res.push(Method::key_down(*key)));
                     keys.iter().for_each(|key| res.push(Method::key_up(*key)));
                 }
-                ScriptEvent::Scroll(delta_x, delta_y) => res.push(Method::Event(EventType::Wheel { delta_x, delta_y })),
-                ScriptEvent::Move(x, y) => res.push(Method::Event(self.mouse_move(x, y))),
-                ScriptEvent::Sleep(n) => res.push(Method::Custom(Custom::Sleep(n))),
+                ScriptEvent::Scroll(delta_x, delta_y) => {
+                    res.push(Method::Scroll(delta_x.compile()?, delta_y.compile()?))
+                }
+                ScriptEvent::Move(x, y, motion) => res.push(self.mouse_move(&x, &y, motion.as_ref())?),
+                ScriptEvent::Path(points) => {
+                    let points = points
+                        .iter()
+                        .map(|(ms, x, y)| Ok((*ms, self.point(x, y)?)))
+                        .collect::<Result<_, Box<dyn Error>>>()?;
+                    res.push(Method::Path(points))
+                }
+                ScriptEvent::Type { text, interval } => res.push(Method::Type {
+                    text,
+                    interval: interval.map(|n| n.compile()).transpose()?.unwrap_or(Expr::Num(0.0)),
+                }),
+                ScriptEvent::Sleep(delay) => res.push(Method::Custom(Custom::Sleep(delay.compile()?))),
+                ScriptEvent::SetVar(name, n) => res.push(Method::Custom(Custom::SetVar(name, n.compile()?))),
+                ScriptEvent::AddVar(name, n) => res.push(Method::Custom(Custom::AddVar(name, n.compile()?))),
+                ScriptEvent::Layer(op, name) => res.push(Method::Custom(Custom::Layer(op, name))),
                 ScriptEvent::Exit => res.push(Method::Custom(Custom::Exit)),
+                ScriptEvent::If { cond, then, r#else } => res.push(Method::If {
+                    cond: cond.compile()?,
+                    then: self.transform(then)?,
+                    r#else: self.transform(r#else)?,
+                }),
+                ScriptEvent::While { cond, body } => {
+                    res.push(Method::While { cond: cond.compile()?, methods: self.transform(body)? })
+                }
+                ScriptEvent::WaitPixel { x, y, color, tolerance, timeout } => res.push(Method::WaitPixel {
+                    pixel: self.pixel(&x, &y, color, tolerance)?,
+                    timeout: timeout.map(|n| n.compile()).transpose()?,
+                }),
+                ScriptEvent::IfPixel { x, y, color, tolerance, then, r#else } => res.push(Method::IfPixel {
+                    pixel: self.pixel(&x, &y, color, tolerance)?,
+                    then: self.transform(then)?,
+                    r#else: self.transform(r#else)?,
+                }),
+                ScriptEvent::FindImage { path, threshold, region, var } => res.push(Method::FindImage {
+                    template: self.template(&path, threshold, region)?,
+                    var,
+                    required: false,
+                }),
+                ScriptEvent::ClickImage { button, path, threshold, region, var } => {
+                    res.push(Method::FindImage {
+                        template: self.template(&path, threshold, region)?,
+                        var: var.clone(),
+                        required: true,
+                    });
+                    let (x, y) = (Num::Expr(format!("${{{var}_x}}")), Num::Expr(format!("${{{var}_y}}")));
+                    res.push(self.mouse_move(&x, &y, None)?);
+                    res.push(Method::mouse_down(button));
+                    res.push(Method::mouse_up(button));
+                }
+                ScriptEvent::SetClipboard { text } => res.push(Method::Custom(Custom::SetClipboard(text))),
+                ScriptEvent::GetClipboard { var } => res.push(Method::Custom(Custom::GetClipboard(var))),
+                ScriptEvent::Exec { cmd, args, wait, timeout, capture } => {
+                    for (name, set) in [("capture", capture.is_some()), ("timeout", timeout.is_some())] {
+                        if !wait && set {
+                            return Err(
+                                format!("命令 {cmd:?} 的 {name} 需要等待结束, 不能和 wait = false 一起使用").into(),
+                            );
+                        }
+                    }
+                    res.push(Method::Custom(Custom::Exec {
+                        cmd,
+                        args,
+                        wait,
+                        timeout: timeout.map(|n| n.compile()).transpose()?,
+                        capture,
+                    }))
+                }
+                ScriptEvent::Paste => {
+                    res.push(Method::key_down(PASTE));
+                    res.push(Method::key_down(Key::KeyV));
+                    res.push(Method::key_up(Key::KeyV));
+                    res.push(Method::key_up(PASTE));
+                }
+                ScriptEvent::Break => res.push(Method::Break),
+                ScriptEvent::Continue => res.push(Method::Continue),
                 ScriptEvent::Block { sleep, repeat, block } => {
                     let block = match block {
                         Block::Name(name) => {
@@ -125,16 +368,15 @@ impl Config {
                         }
                         Block::Block(val) => val,
                     };
-                    let block = self.transform(block)?;
-                    for _ in 0..repeat {
-                        res.extend(block.iter().cloned());
-                        res.push(Method::Custom(Custom::Sleep(sleep)))
-                    }
-                    res.pop();
+                    res.push(Method::Block {
+                        repeat: repeat.compile()?,
+                        sleep: sleep.compile()?,
+                        methods: self.transform(block)?,
+                    });
                 }
             }
         }
-        res.retain(|f| !matches!(f, Method::Custom(Custom::Sleep(0))));
+        res.retain(|f| !matches!(f, Method::Custom(Custom::Sleep(Random::Fixed(Expr::Num(n)))) if *n == 0.0));
         Ok(res)
     }
 }
@@ -151,6 +393,25 @@ pub enum KeyOrButton {
     Mouse(Button),
 }
 
+impl KeyOrButton {
+    /// 是否为修饰键 Shift/Control/Alt/Meta
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self,
+            KeyOrButton::Key(
+                Key::ShiftLeft
+                    | Key::ShiftRight
+                    | Key::ControlLeft
+                    | Key::ControlRight
+                    | Key::Alt
+                    | Key::AltGr
+                    | Key::MetaLeft
+                    | Key::MetaRight
+            )
+        )
+    }
+}
+
 /// 脚本每一项
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScriptItem {
@@ -160,16 +421,140 @@ pub struct ScriptItem {
     /// 循环次数
     pub repeat: usize,
 
-    /// 触发按键
+    /// 触发按键(同时按下); PS: 和 sequence 二选一
+    #[serde(default)]
     pub trigger: Vec<KeyOrButton>,
 
+    /// 按顺序触发的按键, 每一步可以是单个按键或组合键
+    #[serde(default)]
+    pub sequence: Vec<Step>,
+
+    /// 连按次数, 如 2 为双击
+    pub taps: Option<usize>,
+
+    /// 长按多少毫秒后触发
+    pub long_press: Option<u64>,
+
+    /// 连按和按键序列每一步的最大间隔毫秒, 默认 500
+    pub timeout: Option<u64>,
+
+    /// 触发方式
+    #[serde(default)]
+    pub mode: Mode,
+
+    /// 屏蔽最后一步的按键, 不再传给当前窗口
+    #[serde(default)]
+    pub swallow: bool,
+
+    /// 生效的方案名称, 为空时对所有窗口生效
+    #[serde(default)]
+    pub profiles: Vec<String>,
+
+    /// 所在的层, 只在该层位于最上方时触发; 为空时不受层影响
+    pub layer: Option<String>,
+
     /// 单独配置延迟
     pub delay: Option<u64>,
 
+    /// 单独配置 Sleep 和延迟随机增减的毫秒数
+    pub jitter_ms: Option<u64>,
+
+    /// 单独配置鼠标位置随机偏移的像素数
+    pub jitter_px: Option<f64>,
+
     /// 脚本方法
     pub methods: Vec<ScriptEvent>,
 }
 
+impl ScriptItem {
+    /// 生成触发条件
+    pub fn trigger(&self) -> Result<Trigger, Box<dyn Error>> {
+        let steps: Vec<Vec<KeyOrButton>> = match (self.trigger.is_empty(), self.sequence.is_empty()) {
+            (false, true) => vec![self.trigger.clone()],
+            (true, false) => self.sequence.iter().cloned().map(Step::keys).collect(),
+            _ => return Err(format!("脚本 {:?} 需要 trigger 和 sequence 其中之一", self.title).into()),
+        };
+        if steps.iter().any(|step| step.is_empty()) {
+            return Err(format!("脚本 {:?} 的 sequence 不能有空的组合键", self.title).into());
+        }
+        let taps = match self.taps {
+            Some(0) => return Err(format!("脚本 {:?} 的 taps 不能为 0", self.title).into()),
+            Some(n) => n,
+            None => 1,
+        };
+
+        Ok(Trigger {
+            steps: (0..taps).flat_map(|_| steps.iter().cloned()).collect(),
+            long_press: self.long_press.map(Duration::from_millis),
+            timeout: Duration::from_millis(self.timeout.unwrap_or(500)),
+        })
+    }
+}
+
+/// 按键序列中的一步
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Step {
+    /// 单个按键
+    Key(KeyOrButton),
+    /// 组合键
+    Chord(Vec<KeyOrButton>),
+}
+
+impl Step {
+    pub fn keys(self) -> Vec<KeyOrButton> {
+        match self {
+            Step::Key(key) => vec![key],
+            Step::Chord(keys) => keys,
+        }
+    }
+}
+
+/// 方案: 焦点窗口符合条件时生效, 未填写的条件不做限制
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    /// 窗口类名, 忽略大小写
+    pub class: Option<String>,
+    /// 窗口标题包含的文字
+    pub title: Option<String>,
+    /// 进程名, 忽略大小写
+    pub process: Option<String>,
+}
+
+impl Profile {
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        self.class
+            .as_ref()
+            .is_none_or(|class| class.eq_ignore_ascii_case(&window.class))
+            && self
+                .title
+                .as_ref()
+                .is_none_or(|title| window.title.contains(title.as_str()))
+            && self
+                .process
+                .as_ref()
+                .is_none_or(|process| process.eq_ignore_ascii_case(&window.process))
+    }
+}
+
+/// 触发方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// 按下时运行, 运行中再次按下无效
+    Press,
+    /// 松开时运行, 运行中再次松开无效
+    Release,
+    /// 按住时运行, 松开任一触发按键终止
+    Hold,
+    /// 按下时运行, 运行中再次按下终止
+    #[default]
+    Toggle,
+    /// 按下时运行, 运行中再次按下重新开始
+    Restart,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Block {
@@ -189,11 +574,23 @@ pub enum ScriptEvent {
     /// 鼠标按下
     ClickDown(Button),
 
-    /// 点击指定位置
-    ClickOn(Button, f64, f64),
+    /// 点击指定位置; 最后可加移动方式 [`Motion`]
+    ClickOn(
+        Button,
+        Num,
+        Num,
+        #[serde(default, skip_serializing_if = "Option::is_none")] Option<Motion>,
+    ),
 
-    /// 拖拽到指定位置
-    ClickTo(Button, f64, f64, f64, f64),
+    /// 拖拽到指定位置; 最后可加移动方式 [`Motion`], 移动到起点和拖拽都按该方式
+    ClickTo(
+        Button,
+        Num,
+        Num,
+        Num,
+        Num,
+        #[serde(default, skip_serializing_if = "Option::is_none")] Option<Motion>,
+    ),
 
     /// 键盘松开
     KeyUp(Key),
@@ -207,45 +604,424 @@ pub enum ScriptEvent {
     /// 触发多个按键
     Keys(Vec<Key>),
 
-    /// 移动鼠标到指定位置
-    Move(f64, f64),
+    /// 移动鼠标到指定位置; 最后可加移动方式 [`Motion`], 没有时直接跳到该位置
+    Move(
+        Num,
+        Num,
+        #[serde(default, skip_serializing_if = "Option::is_none")] Option<Motion>,
+    ),
+
+    /// 按原速度沿轨迹移动鼠标; 参数: [[距上一个点的毫秒数, x, y], ...]
+    Path(Vec<(u64, Num, Num)>),
 
     /// 滚轮
-    Scroll(i64, i64),
+    Scroll(Num, Num),
+
+    /// 输入文字, `${name}` 替换为变量的值; interval 每个字符之间额外的毫秒数
+    /// 美式键盘上的字符模拟按键(需关闭大写锁定), 其余字符(如中文)在 X11 下通过临时映射 keysym 输入, 其他平台通过剪贴板粘贴后还原剪贴板
+    Type {
+        text: String,
+        interval: Option<Num>,
+    },
 
     /// 自定义事件
     Block {
-        repeat: usize,
-        sleep: u64,
+        repeat: Num,
+        sleep: Num,
         block: Block,
     },
-    Sleep(u64),
+    /// 睡眠; 参数: 毫秒数, [最小, 最大] 或 [`Distribution`]
+    Sleep(Delay),
+
+    /// 设置变量
+    SetVar(String, Num),
+
+    /// 变量增加指定值
+    AddVar(String, Num),
+
+    /// 条件分支; cond 非 0 为真
+    If {
+        cond: Num,
+        then: Vec<ScriptEvent>,
+        #[serde(default)]
+        r#else: Vec<ScriptEvent>,
+    },
+
+    /// 条件循环
+    While {
+        cond: Num,
+        body: Vec<ScriptEvent>,
+    },
+
+    /// 等待指定位置变为指定颜色; tolerance 每个通道允许的误差; timeout 超时毫秒, 超时脚本执行失败
+    WaitPixel {
+        x: Num,
+        y: Num,
+        color: Color,
+        #[serde(default)]
+        tolerance: u8,
+        timeout: Option<Num>,
+    },
+
+    /// 按指定位置的颜色分支
+    IfPixel {
+        x: Num,
+        y: Num,
+        color: Color,
+        #[serde(default)]
+        tolerance: u8,
+        then: Vec<ScriptEvent>,
+        #[serde(default)]
+        r#else: Vec<ScriptEvent>,
+    },
+
+    /// 在屏幕中查找 png 图片; threshold 最低相似度(0~1); region 查找区域 [x, y, 宽, 高]
+    /// 结果写入变量: {var} 是否找到(1/0), {var}_x {var}_y 中心位置
+    FindImage {
+        path: PathBuf,
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+        region: Option<(Num, Num, Num, Num)>,
+        #[serde(default = "default_var")]
+        var: String,
+    },
+
+    /// 查找图片并点击中心位置, 找不到时脚本执行失败; 参数同 FindImage
+    ClickImage {
+        button: Button,
+        path: PathBuf,
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+        region: Option<(Num, Num, Num, Num)>,
+        #[serde(default = "default_var")]
+        var: String,
+    },
+
+    /// 写入剪贴板, `${name}` 替换为变量的值
+    SetClipboard {
+        text: String,
+    },
+
+    /// 读取剪贴板到文本变量 var
+    GetClipboard {
+        var: String,
+    },
+
+    /// 粘贴(Ctrl + V, macOS 为 Cmd + V)
+    Paste,
+
+    /// 运行外部命令, cmd 和 args 中的 `${name}` 替换为变量的值; wait 是否等待命令结束, 默认 true
+    /// timeout 等待的超时毫秒, 超时结束命令且脚本执行失败, 不能和 wait = false 一起使用
+    /// capture 等待结束后把标准输出写入文本变量 {capture}, 退出码写入变量 {capture}_code, 不能和 wait = false 一起使用
+    Exec {
+        cmd: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default = "default_wait")]
+        wait: bool,
+        timeout: Option<Num>,
+        capture: Option<String>,
+    },
+
+    /// 跳出当前循环
+    Break,
+
+    /// 跳过本次循环
+    Continue,
+
+    /// 切换层; 参数: [push 压入 | pop 移除 | toggle 有则移除无则压入, 层名]
+    Layer(LayerOp, String),
     Exit,
 }
 
+/// 层的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerOp {
+    Push,
+    Pop,
+    Toggle,
+}
+
+fn default_threshold() -> f64 {
+    0.9
+}
+
+fn default_var() -> String {
+    "found".into()
+}
+
+fn default_wait() -> bool {
+    true
+}
+
+/// Sleep 的毫秒数
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Delay {
+    Fixed(Num),
+    /// [最小, 最大] 之间均匀分布
+    Range(Num, Num),
+    Distribution(Distribution),
+}
+
+/// 随机分布: `{ distribution = "uniform", min, max }` 或 `{ distribution = "gaussian", mean, std }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum Distribution {
+    Uniform { min: Num, max: Num },
+    Gaussian { mean: Num, std: Num },
+}
+
+impl Delay {
+    pub fn compile(&self) -> Result<Random, String> {
+        Ok(match self {
+            Delay::Fixed(n) => Random::Fixed(n.compile()?),
+            Delay::Range(min, max) | Delay::Distribution(Distribution::Uniform { min, max }) => {
+                Random::Uniform(min.compile()?, max.compile()?)
+            }
+            Delay::Distribution(Distribution::Gaussian { mean, std }) => {
+                Random::Gaussian(mean.compile()?, std.compile()?)
+            }
+        })
+    }
+}
+
+impl From<i64> for Delay {
+    fn from(n: i64) -> Self {
+        Delay::Fixed(n.into())
+    }
+}
+
+/// 鼠标移动方式: 毫秒数(缓入缓出), 或 `{ curve, duration, speed, jitter }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Motion {
+    Duration(Num),
+    Curve {
+        /// linear 匀速 | ease-in-out 缓入缓出(默认) | bezier 随机弯曲
+        #[serde(default)]
+        curve: Curve,
+        /// 总毫秒数
+        duration: Option<Num>,
+        /// 每秒移动的像素数, 没有 duration 时使用; 都没有时为 200 毫秒
+        speed: Option<Num>,
+        /// 随机偏移的像素数; bezier 默认为距离的 1/5, 其余默认为 0
+        jitter: Option<Num>,
+    },
+}
+
+impl Motion {
+    /// 编译为 [`Movement`], 速度和偏移按缩放换算为屏幕像素
+    pub fn compile(&self, scaling: f64) -> Result<Movement, String> {
+        let scale = |n: &Num| Ok::<_, String>(Expr::binary(Op::Div, n.compile()?, Expr::Num(scaling)));
+        Ok(match self {
+            Motion::Duration(duration) => Movement {
+                curve: Curve::default(),
+                duration: Some(duration.compile()?),
+                speed: None,
+                jitter: None,
+            },
+            Motion::Curve { curve, duration, speed, jitter } => Movement {
+                curve: *curve,
+                duration: duration.as_ref().map(Num::compile).transpose()?,
+                speed: speed.as_ref().map(scale).transpose()?,
+                jitter: jitter.as_ref().map(scale).transpose()?,
+            },
+        })
+    }
+}
+
+/// 数值参数: 数字或表达式, 如 `"${x} * 40 + 20"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Num {
+    Int(i64),
+    Float(f64),
+    Expr(String),
+}
+
+impl Num {
+    pub fn compile(&self) -> Result<Expr, String> {
+        match self {
+            Num::Int(n) => Ok(Expr::Num(*n as f64)),
+            Num::Float(n) => Ok(Expr::Num(*n)),
+            Num::Expr(s) => Expr::parse(s).map_err(|err| format!("表达式 {s:?} 有误: {err}")),
+        }
+    }
+}
+
+impl From<i64> for Num {
+    fn from(n: i64) -> Self {
+        Num::Int(n)
+    }
+}
+
+impl From<f64> for Num {
+    fn from(n: f64) -> Self {
+        Num::Float(n)
+    }
+}
+
 /// 自定义事件
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Custom {
-    /// 睡眠 ms 毫秒
-    Sleep(u64),
+    /// 睡眠随机的毫秒数
+    Sleep(Random),
+
+    /// 设置变量
+    SetVar(String, Expr),
+
+    /// 变量增加指定值
+    AddVar(String, Expr),
+
+    /// 切换层
+    Layer(LayerOp, String),
+
+    /// 写入剪贴板
+    SetClipboard(String),
+
+    /// 读取剪贴板到文本变量
+    GetClipboard(String),
+
+    /// 运行外部命令
+    Exec {
+        cmd: String,
+        args: Vec<String>,
+        wait: bool,
+        timeout: Option<Expr>,
+        capture: Option<String>,
+    },
 
     /// 退出
     Exit,
 }
 
 impl Custom {
-    pub async fn run(&self) {
+    pub async fn run(&self, ctx: &mut Context) -> Result<(), String> {
         match self {
-            Custom::Sleep(n) => sleep(Duration::from_millis(*n)).await,
+            Custom::Sleep(delay) => {
+                let ms = delay.sample(&*ctx, &mut ctx.runtime.rng.lock().unwrap())?;
+                sleep(ctx.jitter(ms)).await
+            }
+            Custom::SetVar(name, n) => {
+                let n = n.eval(ctx)?;
+                ctx.vars.insert(name.clone(), n);
+            }
+            Custom::AddVar(name, n) => {
+                let n = n.eval(ctx)?;
+                *ctx.vars.get_mut(name).ok_or_else(|| format!("变量 {name:?} 未定义"))? += n;
+            }
+            Custom::Layer(op, name) => {
+                let mut layers = ctx.runtime.layers.lock().unwrap();
+                let pos = layers.iter().rposition(|layer| layer == name);
+                match (op, pos) {
+                    (LayerOp::Push, _) | (LayerOp::Toggle, None) => layers.push(name.clone()),
+                    (LayerOp::Pop | LayerOp::Toggle, Some(i)) => {
+                        layers.remove(i);
+                    }
+                    (LayerOp::Pop, None) => return Ok(()),
+                }
+                let _ = ctx.notifier.send(Notice::Layers(layers.clone()));
+            }
+            Custom::SetClipboard(text) => {
+                let text = ctx.format(text)?;
+                let clipboard = ctx.runtime.clipboard.clone();
+                spawn_blocking(move || clipboard.set(&text))
+                    .await
+                    .map_err(|err| err.to_string())??;
+            }
+            Custom::GetClipboard(var) => {
+                let clipboard = ctx.runtime.clipboard.clone();
+                let text = spawn_blocking(move || clipboard.get())
+                    .await
+                    .map_err(|err| err.to_string())??;
+                ctx.texts.insert(var.clone(), text);
+            }
+            Custom::Exec { cmd, args, wait, timeout, capture } => {
+                let mut command = Command::new(ctx.format(cmd)?);
+                for arg in args {
+                    command.arg(ctx.format(arg)?);
+                }
+                // 子进程不能读取终端, 否则会抢走终端界面的按键; 不需要的输出也不写到终端上
+                command.stdin(Stdio::null()).stderr(Stdio::null());
+                command.stdout(match capture {
+                    Some(_) => Stdio::piped(),
+                    None => Stdio::null(),
+                });
+                // 等待中脚本被终止或超时时结束命令
+                command.kill_on_drop(*wait);
+                let child = command.spawn().map_err(|err| format!("运行 {cmd:?} 失败: {err}"))?;
+                if !wait {
+                    return Ok(());
+                }
+                let output = match timeout {
+                    Some(n) => {
+                        let timeout = Duration::from_millis(n.eval(ctx)?.max(0.0) as u64);
+                        time::timeout(timeout, child.wait_with_output())
+                            .await
+                            .map_err(|_| format!("运行 {cmd:?} 超时"))?
+                    }
+                    None => child.wait_with_output().await,
+                };
+                let output = output.map_err(|err| format!("运行 {cmd:?} 失败: {err}"))?;
+                if let Some(var) = capture {
+                    let stdout = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+                    ctx.texts.insert(var.clone(), stdout);
+                    ctx.vars
+                        .insert(format!("{var}_code"), output.status.code().unwrap_or(-1) as f64);
+                }
+            }
             Custom::Exit => exit(0),
         }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Method {
     /// 事件
     Event(EventType),
+    /// 移动鼠标, 有移动方式时逐步移动
+    Move(Expr, Expr, Option<Movement>),
+    /// 按原速度沿轨迹移动鼠标, 每个点为距上一个点的毫秒数和位置
+    Path(Vec<(u64, (Expr, Expr))>),
+    /// 滚轮
+    Scroll(Expr, Expr),
+    /// 输入文字
+    Type { text: String, interval: Expr },
+    /// 重复执行的脚本块
+    Block {
+        repeat: Expr,
+        sleep: Expr,
+        methods: Vec<Method>,
+    },
+    /// 条件分支
+    If {
+        cond: Expr,
+        then: Vec<Method>,
+        r#else: Vec<Method>,
+    },
+    /// 条件循环
+    While { cond: Expr, methods: Vec<Method> },
+    /// 等待像素颜色
+    WaitPixel { pixel: Pixel, timeout: Option<Expr> },
+    /// 按像素颜色分支
+    IfPixel {
+        pixel: Pixel,
+        then: Vec<Method>,
+        r#else: Vec<Method>,
+    },
+    /// 查找图片, 结果写入变量; required 为 true 时找不到则执行失败
+    FindImage {
+        template: Template,
+        var: String,
+        required: bool,
+    },
+    /// 跳出循环
+    Break,
+    /// 跳过本次循环
+    Continue,
     /// 自定义
     Custom(Custom),
 }
@@ -0,0 +1,340 @@
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+use rdev::{Button, Key};
+use serde::{
+    de::value::{Error as ValueError, StrDeserializer},
+    Deserialize,
+};
+
+use crate::script::config::KeyOrButton;
+
+/// 脚本变量
+pub type Vars = HashMap<String, f64>;
+
+/// 表达式求值时可访问的环境
+pub trait Scope {
+    /// 变量的值
+    fn var(&self, name: &str) -> Option<f64>;
+
+    /// 按键是否按住
+    fn held(&self, key: &KeyOrButton) -> bool;
+
+    /// 当前循环的次数(从 0 开始)
+    fn index(&self) -> usize;
+}
+
+/// 运算符
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl Op {
+    /// 优先级, 越大越先计算
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+            Op::Add | Op::Sub => 4,
+            Op::Mul | Op::Div | Op::Rem => 5,
+        }
+    }
+
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let bool = |b: bool| if b { 1.0 } else { 0.0 };
+        match self {
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::Rem => a % b,
+            Op::Eq => bool(a == b),
+            Op::Ne => bool(a != b),
+            Op::Lt => bool(a < b),
+            Op::Le => bool(a <= b),
+            Op::Gt => bool(a > b),
+            Op::Ge => bool(a >= b),
+            Op::And => bool(a != 0.0 && b != 0.0),
+            Op::Or => bool(a != 0.0 || b != 0.0),
+        }
+    }
+}
+
+/// 表达式, 如 `${x} * 40 + 20`, `${i} < 10 && held(ShiftLeft)`; 非 0 为真
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    /// 按键是否按住: `held(KeyA)`, `held(Left)`
+    Held(KeyOrButton),
+    /// 当前循环的次数: `index()`
+    Index,
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parser = Parser { chars: s.chars().peekable() };
+        let expr = parser.expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(format!("多余的字符 {c:?}")),
+        }
+    }
+
+    /// 组合两个表达式, 都是常量时直接计算
+    pub fn binary(op: Op, a: Expr, b: Expr) -> Self {
+        match (a, b) {
+            (Expr::Num(a), Expr::Num(b)) => Expr::Num(op.apply(a, b)),
+            (a, b) => Expr::Binary(op, Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn eval(&self, scope: &impl Scope) -> Result<f64, String> {
+        match self {
+            Expr::Num(n) => Ok(*n),
+            Expr::Var(name) => scope.var(name).ok_or_else(|| format!("变量 {name:?} 未定义")),
+            Expr::Neg(expr) => Ok(-expr.eval(scope)?),
+            Expr::Not(expr) => Ok(if expr.test(scope)? { 0.0 } else { 1.0 }),
+            // 逻辑运算短路求值
+            Expr::Binary(Op::And, a, _) if !a.test(scope)? => Ok(0.0),
+            Expr::Binary(Op::Or, a, _) if a.test(scope)? => Ok(1.0),
+            Expr::Binary(Op::And | Op::Or, _, b) => Ok(if b.test(scope)? { 1.0 } else { 0.0 }),
+            Expr::Binary(op, a, b) => Ok(op.apply(a.eval(scope)?, b.eval(scope)?)),
+            Expr::Held(key) => Ok(if scope.held(key) { 1.0 } else { 0.0 }),
+            Expr::Index => Ok(scope.index() as f64),
+        }
+    }
+
+    /// 作为条件求值
+    pub fn test(&self, scope: &impl Scope) -> Result<bool, String> {
+        Ok(self.eval(scope)? != 0.0)
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// 跳过空白后查看下一个字符
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            Some(c) => Err(format!("应为 {expected:?}, 实际为 {c:?}")),
+            None => Err(format!("缺少 {expected:?}")),
+        }
+    }
+
+    /// 下一个运算符及其字符数
+    fn op(&mut self) -> Option<(Op, usize)> {
+        let c = self.peek()?;
+        let mut chars = self.chars.clone();
+        chars.next();
+        let op = match (c, chars.peek()) {
+            ('=', Some('=')) => (Op::Eq, 2),
+            ('!', Some('=')) => (Op::Ne, 2),
+            ('<', Some('=')) => (Op::Le, 2),
+            ('>', Some('=')) => (Op::Ge, 2),
+            ('&', Some('&')) => (Op::And, 2),
+            ('|', Some('|')) => (Op::Or, 2),
+            ('<', _) => (Op::Lt, 1),
+            ('>', _) => (Op::Gt, 1),
+            ('+', _) => (Op::Add, 1),
+            ('-', _) => (Op::Sub, 1),
+            ('*', _) => (Op::Mul, 1),
+            ('/', _) => (Op::Div, 1),
+            ('%', _) => (Op::Rem, 1),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// 解析优先级高于 min 的二元运算
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some((op, len)) = self.op() {
+            if op.precedence() <= min {
+                break;
+            }
+            self.chars.nth(len - 1);
+            let rhs = self.expr(op.precedence())?;
+            lhs = Expr::binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(match self.unary()? {
+                    Expr::Num(n) => Expr::Num(-n),
+                    expr => Expr::Neg(Box::new(expr)),
+                })
+            }
+            Some('!') => {
+                self.chars.next();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let expr = self.expr(0)?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some('$') => {
+                self.chars.next();
+                self.expect('{')?;
+                let name = self.ident();
+                self.expect('}')?;
+                match name.is_empty() {
+                    true => Err("变量名不能为空".into()),
+                    false => Ok(Expr::Var(name)),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut s = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    s.push(c);
+                }
+                s.parse().map(Expr::Num).map_err(|_| format!("无效的数字 {s:?}"))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.ident();
+                if self.peek() != Some('(') {
+                    return Ok(Expr::Var(name));
+                }
+                self.chars.next();
+                let arg = self.ident();
+                self.expect(')')?;
+                match name.as_str() {
+                    "held" => key_or_button(&arg).map(Expr::Held),
+                    "index" if arg.is_empty() => Ok(Expr::Index),
+                    _ => Err(format!("未知的函数 {name}({arg})")),
+                }
+            }
+            Some(c) => Err(format!("无效的字符 {c:?}")),
+            None => Err("表达式不完整".into()),
+        }
+    }
+
+    fn ident(&mut self) -> String {
+        let mut s = String::new();
+        self.peek();
+        while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+            s.push(c);
+        }
+        s
+    }
+}
+
+/// 按名称解析按键, 如 `KeyA`, `ShiftLeft`, `Left`
+pub fn key_or_button(name: &str) -> Result<KeyOrButton, String> {
+    let de = || StrDeserializer::<ValueError>::new(name);
+    Key::deserialize(de())
+        .map(KeyOrButton::Key)
+        .or_else(|_| Button::deserialize(de()).map(KeyOrButton::Mouse))
+        .map_err(|_| format!("未知的按键 {name:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestScope(Vars);
+
+    impl Scope for TestScope {
+        fn var(&self, name: &str) -> Option<f64> {
+            self.0.get(name).copied()
+        }
+
+        fn held(&self, key: &KeyOrButton) -> bool {
+            *key == KeyOrButton::Key(Key::ShiftLeft)
+        }
+
+        fn index(&self) -> usize {
+            3
+        }
+    }
+
+    fn eval(s: &str) -> Result<f64, String> {
+        let scope = TestScope(Vars::from([("x".to_string(), 2.0), ("i".to_string(), 10.0)]));
+        Expr::parse(s)?.eval(&scope)
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3.0));
+        assert_eq!(eval("17 % 5 / 2"), Ok(1.0));
+        assert_eq!(eval("-2 * -(1 + 2)"), Ok(6.0));
+        assert_eq!(eval(" 1.5+.5 "), Ok(2.0));
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("100 + ${i} % 8 * 40"), Ok(180.0));
+        assert_eq!(eval("${x} * ${x}"), Ok(4.0));
+        assert!(eval("${y} + 1").unwrap_err().contains("\"y\""));
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(eval("${x} >= 2 && ${i} != 10"), Ok(0.0));
+        assert_eq!(eval("${x} < 1 || ${i} == 10"), Ok(1.0));
+        assert_eq!(eval("1 + 1 == 2"), Ok(1.0));
+        assert_eq!(eval("!(${x} > 1)"), Ok(0.0));
+        assert_eq!(eval("held(ShiftLeft) && !held(Left)"), Ok(1.0));
+        assert_eq!(eval("index() < 5"), Ok(1.0));
+        assert!(eval("held(Nope)").is_err());
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        // 右侧的变量未定义, 短路时不会求值
+        assert_eq!(eval("0 && ${y}"), Ok(0.0));
+        assert_eq!(eval("1 || ${y}"), Ok(1.0));
+        assert!(eval("1 && ${y}").is_err());
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        assert_eq!(Expr::parse("2 * (3 + 4)"), Ok(Expr::Num(14.0)));
+        assert_eq!(
+            Expr::parse("${x} + 1"),
+            Ok(Expr::binary(Op::Add, Expr::Var("x".into()), Expr::Num(1.0)))
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for s in ["", "1 +", "(1 + 2", "1 2", "${}", "1 # 2", "foo(1)", "1..2"] {
+            assert!(Expr::parse(s).is_err(), "{s:?}");
+        }
+    }
+}
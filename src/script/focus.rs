@@ -0,0 +1,177 @@
+use std::sync::Mutex;
+
+/// 窗口信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// 窗口类名(WM_CLASS)
+    pub class: String,
+    /// 窗口标题
+    pub title: String,
+    /// 进程名
+    pub process: String,
+}
+
+/// 当前焦点窗口的来源
+pub trait FocusProvider: Send + Sync {
+    /// 当前焦点窗口, 没有时为 None
+    fn focused(&self) -> Result<Option<WindowInfo>, String>;
+}
+
+/// 手动设置的焦点窗口
+#[derive(Debug, Default)]
+pub struct MockFocus(Mutex<Option<WindowInfo>>);
+
+impl MockFocus {
+    pub fn new(window: Option<WindowInfo>) -> Self {
+        Self(Mutex::new(window))
+    }
+
+    /// 切换焦点窗口
+    pub fn set(&self, window: Option<WindowInfo>) {
+        *self.0.lock().unwrap() = window;
+    }
+}
+
+impl FocusProvider for MockFocus {
+    fn focused(&self) -> Result<Option<WindowInfo>, String> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+}
+
+/// X11 焦点窗口, 通过 _NET_ACTIVE_WINDOW 查询; 复用同一个连接
+#[cfg(target_os = "linux")]
+#[derive(Debug, Default)]
+pub struct X11Focus {
+    display: crate::script::xconn::XConnection,
+}
+
+#[cfg(target_os = "linux")]
+impl FocusProvider for X11Focus {
+    fn focused(&self) -> Result<Option<WindowInfo>, String> {
+        use std::{ffi::CStr, fs, mem};
+
+        use x11::xlib;
+
+        self.display.with(|display| unsafe {
+            // 焦点窗口可能在查询过程中关闭, 连接上的 BadWindow 错误只做记录, 查不到的属性为空
+            let root = xlib::XDefaultRootWindow(display);
+            let window = prop::property(display, root, c"_NET_ACTIVE_WINDOW", xlib::XA_WINDOW, prop::first);
+            let Some(window) = window.filter(|w| *w != 0) else {
+                return Ok(None);
+            };
+
+            let mut hint: xlib::XClassHint = mem::zeroed();
+            let mut class = String::new();
+            if xlib::XGetClassHint(display, window, &mut hint) != 0 {
+                if !hint.res_class.is_null() {
+                    class = CStr::from_ptr(hint.res_class).to_string_lossy().into_owned();
+                    xlib::XFree(hint.res_class.cast());
+                }
+                if !hint.res_name.is_null() {
+                    xlib::XFree(hint.res_name.cast());
+                }
+            }
+
+            let utf8 = xlib::XInternAtom(display, c"UTF8_STRING".as_ptr(), xlib::False);
+            let title = prop::property(display, window, c"_NET_WM_NAME", utf8, prop::string)
+                .or_else(|| prop::property(display, window, c"WM_NAME", xlib::XA_STRING, prop::string))
+                .unwrap_or_default();
+
+            let pid = prop::property(display, window, c"_NET_WM_PID", xlib::XA_CARDINAL, prop::first);
+            let process = pid
+                .and_then(|pid| fs::read_to_string(format!("/proc/{pid}/comm")).ok())
+                .map(|comm| comm.trim_end().to_string())
+                .unwrap_or_default();
+
+            Ok(Some(WindowInfo { class, title, process }))
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod prop {
+    use std::{
+        ffi::CStr,
+        mem::size_of,
+        os::raw::{c_int, c_short, c_ulong},
+        ptr, slice,
+    };
+
+    use x11::xlib;
+
+    /// 读取窗口属性, read 的参数为格式(8/16/32)和数据
+    pub unsafe fn property<T>(
+        display: *mut xlib::Display,
+        window: xlib::Window,
+        name: &CStr,
+        ty: xlib::Atom,
+        read: impl FnOnce(c_int, &[u8]) -> Option<T>,
+    ) -> Option<T> {
+        let atom = xlib::XInternAtom(display, name.as_ptr(), xlib::True);
+        if atom == 0 {
+            return None;
+        }
+        let (mut actual, mut format, mut len, mut remain) = (0, 0, 0, 0);
+        let mut data = ptr::null_mut();
+        let status = xlib::XGetWindowProperty(
+            display,
+            window,
+            atom,
+            0,
+            1024,
+            xlib::False,
+            ty,
+            &mut actual,
+            &mut format,
+            &mut len,
+            &mut remain,
+            &mut data,
+        );
+        if status != xlib::Success as c_int || data.is_null() {
+            return None;
+        }
+        // 32 位的数据在 Xlib 中按 long 存放
+        let size = match format {
+            32 => size_of::<c_ulong>(),
+            16 => size_of::<c_short>(),
+            _ => 1,
+        };
+        let value = match actual == ty {
+            true => read(format, slice::from_raw_parts(data, len as usize * size)),
+            false => None,
+        };
+        xlib::XFree(data.cast());
+        value
+    }
+
+    /// 32 位属性的第一项
+    pub fn first(format: c_int, data: &[u8]) -> Option<c_ulong> {
+        let bytes = data.get(..size_of::<c_ulong>())?;
+        (format == 32).then(|| c_ulong::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// 8 位属性作为字符串
+    pub fn string(format: c_int, data: &[u8]) -> Option<String> {
+        (format == 8).then(|| String::from_utf8_lossy(data).into_owned())
+    }
+}
+
+/// 不支持查询焦点窗口的平台
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UnsupportedFocus;
+
+#[cfg(not(target_os = "linux"))]
+impl FocusProvider for UnsupportedFocus {
+    fn focused(&self) -> Result<Option<WindowInfo>, String> {
+        Err("当前平台暂不支持查询焦点窗口".into())
+    }
+}
+
+/// 当前平台的焦点窗口
+#[cfg(target_os = "linux")]
+pub type DefaultFocus = X11Focus;
+
+/// 当前平台的焦点窗口
+#[cfg(not(target_os = "linux"))]
+pub type DefaultFocus = UnsupportedFocus;
@@ -0,0 +1,365 @@
+use std::{
+    collections::HashSet,
+    mem,
+    os::raw::{c_int, c_uint},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Mutex, OnceLock,
+    },
+    thread,
+};
+
+use rdev::{Button, EventType, Key};
+use x11::{xlib, xtest};
+
+use crate::script::{config::KeyOrButton, trigger::Swallow, xconn};
+
+enum Request {
+    /// 替换屏蔽的按键
+    Swallow(HashSet<Swallow>, Sender<Result<(), String>>),
+    /// 模拟按下被屏蔽的按键
+    Press(KeyOrButton, Sender<()>),
+}
+
+static GRABBER: OnceLock<Sender<Request>> = OnceLock::new();
+
+/// 当前屏蔽的按键, 模拟按下时需要先解除
+static GRABBED: Mutex<Vec<KeyOrButton>> = Mutex::new(Vec::new());
+
+/// 大写锁定和数字锁定, 按住与否都要屏蔽
+const LOCKS: [c_uint; 4] = [0, xlib::LockMask, xlib::Mod2Mask, xlib::LockMask | xlib::Mod2Mask];
+
+/// 通过 XGrabKey/XGrabButton 屏蔽按键, 替换之前屏蔽的按键
+///
+/// 被屏蔽的按键仍能通过 XRecord 监听到, 但不会再传给当前窗口
+pub fn swallow(keys: &HashSet<Swallow>) -> Result<(), String> {
+    if keys.is_empty() && GRABBER.get().is_none() {
+        return Ok(());
+    }
+    let (tx, rx) = mpsc::channel();
+    send(Request::Swallow(keys.clone(), tx))?;
+    rx.recv().map_err(|_| closed())?
+}
+
+/// 模拟按下被屏蔽的按键, 不是被屏蔽的按键时返回 None
+///
+/// 被动抓取也会拦截 XTest 模拟的按键, 在同一个连接上先解除抓取, 模拟后再恢复, 保证顺序
+pub fn press(event_type: &EventType) -> Option<Result<(), String>> {
+    let key = match *event_type {
+        EventType::KeyPress(key) => KeyOrButton::Key(key),
+        EventType::ButtonPress(button) => KeyOrButton::Mouse(button),
+        _ => return None,
+    };
+    if !GRABBED.lock().unwrap().contains(&key) {
+        return None;
+    }
+    let (tx, rx) = mpsc::channel();
+    Some(send(Request::Press(key, tx)).and_then(|_| rx.recv().map_err(|_| closed())))
+}
+
+fn send(request: Request) -> Result<(), String> {
+    let grabber = GRABBER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run(rx));
+        tx
+    });
+    grabber.send(request).map_err(|_| closed())
+}
+
+fn closed() -> String {
+    "屏蔽按键的线程已退出".to_string()
+}
+
+/// 持有 X11 连接, 处理抓取到的事件和屏蔽请求
+fn run(rx: Receiver<Request>) {
+    unsafe {
+        let display = match xconn::open() {
+            Ok(display) => display,
+            Err(err) => {
+                for request in rx {
+                    if let Request::Swallow(_, reply) = request {
+                        let _ = reply.send(Err(err.clone()));
+                    }
+                }
+                return;
+            }
+        };
+        let root = xlib::XDefaultRootWindow(display);
+        let mut fd = libc::pollfd {
+            fd: xlib::XConnectionNumber(display),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let mut grabbed = HashSet::new();
+
+        loop {
+            while xlib::XPending(display) > 0 {
+                let mut event: xlib::XEvent = mem::zeroed();
+                xlib::XNextEvent(display, &mut event);
+                // 按下被屏蔽的按键会激活整个键盘/鼠标的抓取, 立即释放, 避免按住期间的其他按键(包括模拟的)也被屏蔽
+                match event.get_type() {
+                    xlib::KeyPress => xlib::XUngrabKeyboard(display, xlib::CurrentTime),
+                    xlib::ButtonPress => xlib::XUngrabPointer(display, xlib::CurrentTime),
+                    _ => 0,
+                };
+                xlib::XFlush(display);
+            }
+
+            match rx.try_recv() {
+                Ok(Request::Swallow(keys, reply)) => {
+                    let _ = reply.send(grab(display, root, &mut grabbed, keys));
+                }
+                Ok(Request::Press(key, reply)) => {
+                    simulate(display, root, &grabbed, key);
+                    let _ = reply.send(());
+                }
+                Err(TryRecvError::Empty) => {
+                    libc::poll(&mut fd, 1, 50);
+                }
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        xconn::close(display);
+    }
+}
+
+/// 释放之前抓取的按键, 抓取新的按键
+unsafe fn grab(
+    display: *mut xlib::Display,
+    root: xlib::Window,
+    grabbed: &mut HashSet<Swallow>,
+    keys: HashSet<Swallow>,
+) -> Result<(), String> {
+    for swallow in grabbed.drain() {
+        ungrab_one(display, root, &swallow.key);
+    }
+
+    // 按键已被其他程序抓取时会产生 BadAccess 错误
+    let (unknown, failed) = xconn::checked(display, || {
+        let unknown = keys.iter().filter(|swallow| !grab_one(display, root, swallow));
+        unknown.map(|swallow| swallow.to_string()).collect::<Vec<_>>()
+    });
+    *GRABBED.lock().unwrap() = keys.iter().map(|s| s.key.clone()).collect();
+    *grabbed = keys;
+
+    if !unknown.is_empty() {
+        return Err(format!(
+            "无法屏蔽按键 {}, 组合键中的其他按键只能是 Shift/Control/Alt/Meta",
+            unknown.join(", ")
+        ));
+    }
+    match failed {
+        true => Err("部分按键已被其他程序占用, 无法屏蔽".into()),
+        false => Ok(()),
+    }
+}
+
+/// 只在按住修饰键时抓取, 无法抓取时返回 false
+unsafe fn grab_one(display: *mut xlib::Display, root: xlib::Window, swallow: &Swallow) -> bool {
+    let mut mask = 0;
+    for key in swallow.modifiers.iter() {
+        match modifier_mask(key) {
+            Some(m) => mask |= m,
+            None => return false,
+        }
+    }
+    for lock in LOCKS {
+        match swallow.key {
+            KeyOrButton::Key(key) => {
+                let Some(code) = keycode(key) else { return false };
+                xlib::XGrabKey(
+                    display,
+                    code,
+                    mask | lock,
+                    root,
+                    xlib::False,
+                    xlib::GrabModeAsync,
+                    xlib::GrabModeAsync,
+                );
+            }
+            KeyOrButton::Mouse(button) => {
+                xlib::XGrabButton(
+                    display,
+                    button_code(button),
+                    mask | lock,
+                    root,
+                    xlib::False,
+                    (xlib::ButtonPressMask | xlib::ButtonReleaseMask) as c_uint,
+                    xlib::GrabModeAsync,
+                    xlib::GrabModeAsync,
+                    0,
+                    0,
+                );
+            }
+        }
+    }
+    true
+}
+
+unsafe fn ungrab_one(display: *mut xlib::Display, root: xlib::Window, key: &KeyOrButton) {
+    match *key {
+        KeyOrButton::Key(key) => {
+            if let Some(code) = keycode(key) {
+                xlib::XUngrabKey(display, code, xlib::AnyModifier, root);
+            }
+        }
+        KeyOrButton::Mouse(button) => {
+            xlib::XUngrabButton(display, button_code(button), xlib::AnyModifier, root);
+        }
+    }
+}
+
+/// 解除抓取后模拟按下, 再重新抓取
+unsafe fn simulate(display: *mut xlib::Display, root: xlib::Window, grabbed: &HashSet<Swallow>, key: KeyOrButton) {
+    ungrab_one(display, root, &key);
+    match key {
+        KeyOrButton::Key(k) => {
+            if let Some(code) = keycode(k) {
+                xtest::XTestFakeKeyEvent(display, code as c_uint, xlib::True, 0);
+            }
+        }
+        KeyOrButton::Mouse(button) => {
+            xtest::XTestFakeButtonEvent(display, button_code(button), xlib::True, 0);
+        }
+    }
+    xconn::checked(display, || {
+        for swallow in grabbed.iter().filter(|s| s.key == key) {
+            grab_one(display, root, swallow);
+        }
+    });
+}
+
+/// 修饰键对应的 X11 修饰位
+fn modifier_mask(key: &KeyOrButton) -> Option<c_uint> {
+    let mask = match key {
+        KeyOrButton::Key(Key::ShiftLeft | Key::ShiftRight) => xlib::ShiftMask,
+        KeyOrButton::Key(Key::ControlLeft | Key::ControlRight) => xlib::ControlMask,
+        KeyOrButton::Key(Key::Alt) => xlib::Mod1Mask,
+        KeyOrButton::Key(Key::MetaLeft | Key::MetaRight) => xlib::Mod4Mask,
+        KeyOrButton::Key(Key::AltGr) => xlib::Mod5Mask,
+        _ => return None,
+    };
+    Some(mask)
+}
+
+fn button_code(button: Button) -> c_uint {
+    match button {
+        Button::Left => 1,
+        Button::Middle => 2,
+        Button::Right => 3,
+        Button::Unknown(code) => code as c_uint,
+    }
+}
+
+/// X11 键码, 与 rdev 的对应关系一致
+#[rustfmt::skip]
+fn keycode(key: Key) -> Option<c_int> {
+    let code = match key {
+        Key::Alt => 64,
+        Key::AltGr => 108,
+        Key::Backspace => 22,
+        Key::CapsLock => 66,
+        Key::ControlLeft => 37,
+        Key::ControlRight => 105,
+        Key::Delete => 119,
+        Key::DownArrow => 116,
+        Key::End => 115,
+        Key::Escape => 9,
+        Key::F1 => 67,
+        Key::F10 => 76,
+        Key::F11 => 95,
+        Key::F12 => 96,
+        Key::F2 => 68,
+        Key::F3 => 69,
+        Key::F4 => 70,
+        Key::F5 => 71,
+        Key::F6 => 72,
+        Key::F7 => 73,
+        Key::F8 => 74,
+        Key::F9 => 75,
+        Key::Home => 110,
+        Key::LeftArrow => 113,
+        Key::MetaLeft => 133,
+        Key::PageDown => 117,
+        Key::PageUp => 112,
+        Key::Return => 36,
+        Key::RightArrow => 114,
+        Key::ShiftLeft => 50,
+        Key::ShiftRight => 62,
+        Key::Space => 65,
+        Key::Tab => 23,
+        Key::UpArrow => 111,
+        Key::PrintScreen => 107,
+        Key::ScrollLock => 78,
+        Key::Pause => 127,
+        Key::NumLock => 77,
+        Key::BackQuote => 49,
+        Key::Num1 => 10,
+        Key::Num2 => 11,
+        Key::Num3 => 12,
+        Key::Num4 => 13,
+        Key::Num5 => 14,
+        Key::Num6 => 15,
+        Key::Num7 => 16,
+        Key::Num8 => 17,
+        Key::Num9 => 18,
+        Key::Num0 => 19,
+        Key::Minus => 20,
+        Key::Equal => 21,
+        Key::KeyQ => 24,
+        Key::KeyW => 25,
+        Key::KeyE => 26,
+        Key::KeyR => 27,
+        Key::KeyT => 28,
+        Key::KeyY => 29,
+        Key::KeyU => 30,
+        Key::KeyI => 31,
+        Key::KeyO => 32,
+        Key::KeyP => 33,
+        Key::LeftBracket => 34,
+        Key::RightBracket => 35,
+        Key::KeyA => 38,
+        Key::KeyS => 39,
+        Key::KeyD => 40,
+        Key::KeyF => 41,
+        Key::KeyG => 42,
+        Key::KeyH => 43,
+        Key::KeyJ => 44,
+        Key::KeyK => 45,
+        Key::KeyL => 46,
+        Key::SemiColon => 47,
+        Key::Quote => 48,
+        Key::BackSlash => 51,
+        Key::IntlBackslash => 94,
+        Key::KeyZ => 52,
+        Key::KeyX => 53,
+        Key::KeyC => 54,
+        Key::KeyV => 55,
+        Key::KeyB => 56,
+        Key::KeyN => 57,
+        Key::KeyM => 58,
+        Key::Comma => 59,
+        Key::Dot => 60,
+        Key::Slash => 61,
+        Key::Insert => 118,
+        Key::KpReturn => 104,
+        Key::KpMinus => 82,
+        Key::KpPlus => 86,
+        Key::KpMultiply => 63,
+        Key::KpDivide => 106,
+        Key::Kp0 => 90,
+        Key::Kp1 => 87,
+        Key::Kp2 => 88,
+        Key::Kp3 => 89,
+        Key::Kp4 => 83,
+        Key::Kp5 => 84,
+        Key::Kp6 => 85,
+        Key::Kp7 => 79,
+        Key::Kp8 => 80,
+        Key::Kp9 => 81,
+        Key::KpDelete => 91,
+        Key::Unknown(code) => return Some(code as c_int),
+        _ => return None,
+    };
+    Some(code)
+}
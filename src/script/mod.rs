@@ -1,76 +1,421 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::Arc,
-    time::Duration,
+    future::Future,
+    mem,
+    pin::Pin,
+    slice,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use rdev::{listen, simulate, Event, EventType, ListenError};
+use rdev::{Event, EventType, Key, ListenError};
 use tokio::{
-    sync::{mpsc, mpsc::UnboundedSender},
-    task::JoinHandle,
-    time::sleep,
+    sync::{
+        mpsc,
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::{spawn_blocking, yield_now, JoinHandle},
+    time::{interval, sleep, sleep_until},
 };
 
-use crate::script::config::{KeyOrButton, Method};
+use crate::script::{
+    backend::InputBackend,
+    clipboard::{Clipboard, DefaultClipboard},
+    config::{KeyOrButton, Method, Mode, Profile},
+    expr::{Scope, Vars},
+    focus::{DefaultFocus, FocusProvider},
+    motion::STEP,
+    random::Rng,
+    screen::{DefaultScreen, Pixel, ScreenCapture},
+    trigger::{Action, Matcher, Swallow},
+    window::Notice,
+};
 
+pub mod backend;
+pub mod clipboard;
 pub mod config;
+pub mod expr;
+pub mod focus;
+#[cfg(target_os = "linux")]
+mod grab;
+pub mod motion;
+pub mod random;
+pub mod screen;
+pub mod trigger;
+pub mod typing;
 pub mod window;
+#[cfg(target_os = "linux")]
+mod xconn;
 
-pub type Title = (Arc<String>, bool);
+/// 脚本的运行状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub title: Arc<String>,
+    pub state: State,
+    /// 本次运行已完成的循环次数
+    pub iteration: usize,
+    /// 循环次数, 0 为无限循环
+    pub repeat: usize,
+    /// 当前执行的最外层方法的序号
+    pub method: usize,
+    /// 最外层方法的数量
+    pub methods: usize,
+    /// 本次运行开始的时间
+    pub started: Option<Instant>,
+    /// 本次运行失败的原因
+    pub error: Option<Arc<String>>,
+}
+
+impl Status {
+    pub fn new(title: Arc<String>) -> Self {
+        Self {
+            title,
+            state: State::Idle,
+            iteration: 0,
+            repeat: 0,
+            method: 0,
+            methods: 0,
+            started: None,
+            error: None,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// 有限循环时已完成的比例
+    pub fn progress(&self) -> Option<f64> {
+        let done = self.iteration as f64 + self.method as f64 / self.methods.max(1) as f64;
+        (self.repeat > 0).then(|| (done / self.repeat as f64).min(1.0))
+    }
+
+    /// 本次运行已用的时间
+    pub fn elapsed(&self) -> Duration {
+        self.started.map_or(Duration::ZERO, |started| started.elapsed())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// 未运行
+    Idle,
+    /// 正在执行第一轮
+    Running,
+    /// 已完成至少一轮, 继续循环中
+    Looping,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Running => "running",
+            State::Looping => "looping",
+        }
+    }
+}
+
+/// 控制脚本列表的指令
+#[derive(Debug)]
+pub enum Control {
+    /// 替换为重新加载的脚本列表
+    Reload(ScriptList),
+    /// 启动指定标题的脚本
+    Start(String, oneshot::Sender<Result<(), String>>),
+    /// 终止指定标题的脚本
+    Stop(String, oneshot::Sender<Result<(), String>>),
+    /// 终止所有运行中的脚本
+    StopAll(oneshot::Sender<()>),
+    /// 查询脚本列表的状态
+    Status(oneshot::Sender<Snapshot>),
+}
+
+/// 脚本列表的状态
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// 所有脚本的运行状态及触发按键, 按配置中的顺序
+    pub scripts: Vec<(Status, String)>,
+    /// 当前生效的方案
+    pub profile: Option<Arc<String>>,
+    /// 当前的层
+    pub layers: Vec<String>,
+}
 
 /// 脚本列表
-pub struct ScriptList(pub Vec<Script>);
+#[derive(Debug)]
+pub struct ScriptList {
+    pub scripts: Vec<Script>,
+    /// 按焦点窗口切换的方案
+    pub profiles: Vec<Profile>,
+    /// 初始的层
+    pub layers: Vec<String>,
+    /// 随机数种子
+    pub seed: Option<u64>,
+    /// 当前生效的方案
+    pub profile: Option<Arc<String>>,
+    /// 上次查询焦点窗口是否失败, 避免重复提示
+    pub focus_error: bool,
+    /// 当前屏蔽的按键
+    pub swallowed: HashSet<Swallow>,
+    pub notifier: UnboundedSender<Notice>,
+}
 
 impl ScriptList {
     /// 监听脚本的触发
-    pub fn listening(mut self) -> Result<(), ListenError> {
+    pub fn listening(mut self, runtime: Runtime, mut control: UnboundedReceiver<Control>) -> Result<(), ListenError> {
         let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
 
+        let backend = runtime.backend.clone();
         tokio::spawn(async move {
-            let triggers: HashSet<KeyOrButton> = self.0.iter().flat_map(|m| m.trigger.keys().cloned()).collect();
-
-            while let Some(event) = rx.recv().await {
-                match event.event_type {
-                    EventType::KeyPress(key) => {
-                        let key = KeyOrButton::Key(key);
-                        if triggers.contains(&key) {
-                            for item in self.0.iter_mut() {
-                                item.down(&key)
-                            }
-                        }
-                    }
-                    EventType::KeyRelease(key) => {
-                        let key = KeyOrButton::Key(key);
-                        if triggers.contains(&key) {
-                            for item in self.0.iter_mut() {
-                                item.up(&key)
-                            }
-                        }
-                    }
-                    EventType::ButtonPress(button) => {
-                        let button = KeyOrButton::Mouse(button);
-                        if triggers.contains(&button) {
-                            for item in self.0.iter_mut() {
-                                item.down(&button)
-                            }
-                        }
+            let mut triggers = self.triggers();
+            self.seed(&runtime);
+            self.reset_layers(&runtime);
+            self.refresh(&runtime).await;
+            self.announce();
+            let mut focus = interval(Duration::from_millis(500));
+
+            loop {
+                // 最近一个等待长按的时间
+                let deadline = self.scripts.iter().filter_map(|m| m.trigger.deadline()).min();
+
+                tokio::select! {
+                    Some(event) = rx.recv() => self.dispatch(event.event_type, &triggers, &runtime).await,
+                    _ = sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => {
+                        self.tick(&runtime)
                     }
-                    EventType::ButtonRelease(button) => {
-                        let button = KeyOrButton::Mouse(button);
-                        if triggers.contains(&button) {
-                            for item in self.0.iter_mut() {
-                                item.up(&button)
-                            }
+                    _ = focus.tick(), if self.watching() => self.refresh(&runtime).await,
+                    Some(control) = control.recv() => match control {
+                        Control::Reload(list) => {
+                            self.reload(list);
+                            self.announce();
+                            triggers = self.triggers();
+                            self.seed(&runtime);
+                            self.reset_layers(&runtime);
+                            self.refresh(&runtime).await;
                         }
-                    }
-                    _ => {}
+                        control => self.control(control, &runtime),
+                    },
+                    else => break,
                 }
             }
         });
 
-        listen(move |event| {
+        backend.listen(Box::new(move |event| {
             let _ = tx.send(event);
-        })
+        }))
+    }
+
+    /// 处理启动, 终止和查询的指令
+    fn control(&mut self, control: Control, runtime: &Runtime) {
+        match control {
+            Control::Reload(_) => {}
+            Control::Start(title, reply) => {
+                let res = self.script(&title).and_then(|item| match item.running() {
+                    true => Err(format!("脚本 {title:?} 已在运行")),
+                    false => {
+                        item.start(runtime);
+                        Ok(())
+                    }
+                });
+                let _ = reply.send(res);
+            }
+            Control::Stop(title, reply) => {
+                let _ = reply.send(self.script(&title).map(Script::stop));
+            }
+            Control::StopAll(reply) => {
+                self.scripts.iter_mut().for_each(Script::stop);
+                let _ = reply.send(());
+            }
+            Control::Status(reply) => {
+                let _ = reply.send(Snapshot {
+                    scripts: self
+                        .scripts
+                        .iter()
+                        .map(|m| (m.status(), m.trigger.trigger.to_string()))
+                        .collect(),
+                    profile: self.profile.clone(),
+                    layers: runtime.layers.lock().unwrap().clone(),
+                });
+            }
+        }
+    }
+
+    /// 通知界面所有脚本及其顺序
+    fn announce(&self) {
+        let _ = self
+            .notifier
+            .send(Notice::Scripts(self.scripts.iter().map(|m| m.status.clone()).collect()));
+    }
+
+    /// 指定标题的脚本
+    fn script(&mut self, title: &str) -> Result<&mut Script, String> {
+        self.scripts
+            .iter_mut()
+            .find(|m| m.title.as_str() == title)
+            .ok_or_else(|| format!("没有标题为 {title:?} 的脚本"))
+    }
+
+    /// 所有脚本的触发按键
+    fn triggers(&self) -> HashSet<KeyOrButton> {
+        self.scripts
+            .iter()
+            .flat_map(|m| m.trigger.trigger.keys().cloned())
+            .collect()
+    }
+
+    /// 屏蔽当前方案和层下生效的, 开启了 swallow 的脚本最后一步的按键
+    fn swallow(&mut self, runtime: &Runtime) {
+        let layer = runtime.layers.lock().unwrap().last().cloned();
+        let keys: HashSet<Swallow> = self
+            .scripts
+            .iter()
+            .filter(|m| m.swallow && m.active(self.profile.as_deref(), layer.as_ref()))
+            .filter_map(|m| m.trigger.trigger.swallow())
+            .collect();
+        if keys == self.swallowed {
+            return;
+        }
+        self.swallowed = keys;
+        if let Err(err) = runtime.backend.swallow(&self.swallowed) {
+            let _ = self.notifier.send(Notice::Message(format!("屏蔽按键失败: {err}")));
+        }
+    }
+
+    /// 按配置的种子重置随机数
+    fn seed(&self, runtime: &Runtime) {
+        if let Some(seed) = self.seed {
+            *runtime.rng.lock().unwrap() = Rng::new(seed);
+        }
+    }
+
+    /// 恢复为配置的初始层
+    fn reset_layers(&self, runtime: &Runtime) {
+        *runtime.layers.lock().unwrap() = self.layers.clone();
+        let _ = self.notifier.send(Notice::Layers(self.layers.clone()));
+    }
+
+    /// 是否需要定时刷新: 方案随焦点窗口变化, 屏蔽的按键随方案和层变化
+    fn watching(&self) -> bool {
+        !self.profiles.is_empty() || self.scripts.iter().any(|m| m.swallow && m.layer.is_some())
+    }
+
+    /// 按焦点窗口更新当前方案, 取第一个符合的方案; 并更新屏蔽的按键
+    async fn refresh(&mut self, runtime: &Runtime) {
+        if !self.profiles.is_empty() {
+            self.refresh_profile(runtime).await;
+        }
+        self.swallow(runtime);
+    }
+
+    /// 查询焦点窗口需要与 X 服务往返, 放到阻塞线程中, 不占用监听的任务
+    async fn refresh_profile(&mut self, runtime: &Runtime) {
+        let focus = runtime.focus.clone();
+        let focused = spawn_blocking(move || focus.focused())
+            .await
+            .unwrap_or_else(|err| Err(err.to_string()));
+        let window = match focused {
+            Ok(window) => {
+                self.focus_error = false;
+                window
+            }
+            Err(err) => {
+                if !self.focus_error {
+                    let _ = self.notifier.send(Notice::Message(format!("查询焦点窗口失败: {err}")));
+                }
+                self.focus_error = true;
+                None
+            }
+        };
+        let profile = window
+            .and_then(|window| self.profiles.iter().find(|p| p.matches(&window)))
+            .map(|p| Arc::new(p.name.clone()));
+        if profile != self.profile {
+            self.profile = profile;
+            let _ = self.notifier.send(Notice::Profile(self.profile.clone()));
+        }
+    }
+
+    /// 分发事件到脚本
+    async fn dispatch(&mut self, event_type: EventType, triggers: &HashSet<KeyOrButton>, runtime: &Runtime) {
+        let (key, down) = match event_type {
+            EventType::KeyPress(key) => (KeyOrButton::Key(key), true),
+            EventType::KeyRelease(key) => (KeyOrButton::Key(key), false),
+            EventType::ButtonPress(button) => (KeyOrButton::Mouse(button), true),
+            EventType::ButtonRelease(button) => (KeyOrButton::Mouse(button), false),
+            EventType::MouseMove { x, y } => {
+                *runtime.cursor.lock().unwrap() = (x, y);
+                return;
+            }
+            _ => return,
+        };
+        let repeated = {
+            let mut held = runtime.held.lock().unwrap();
+            // 长按时系统重复发送的按下事件
+            let repeated = down && !held.insert(key.clone());
+            if !down {
+                held.remove(&key);
+            }
+            repeated
+        };
+        // 其他按键的按下也要传给状态机, 打断未完成的按键序列
+        let trigger = triggers.contains(&key);
+        if repeated || (!down && !trigger) {
+            return;
+        }
+        // 按下时焦点窗口可能刚切换, 重新查询
+        if down && trigger && !self.profiles.is_empty() {
+            self.refresh(runtime).await;
+        }
+
+        // 复制一份, 避免启动脚本时仍持有锁
+        let held = runtime.held.lock().unwrap().clone();
+        let layer = runtime.layers.lock().unwrap().last().cloned();
+        let now = Instant::now();
+        for item in self.scripts.iter_mut() {
+            let action = match down {
+                true if item.active(self.profile.as_deref(), layer.as_ref()) => item.trigger.down(&key, &held, now),
+                true => None,
+                false => item.trigger.up(&key),
+            };
+            if let Some(action) = action {
+                item.handle(action, runtime)
+            }
+        }
+    }
+
+    /// 检查长按触发
+    fn tick(&mut self, runtime: &Runtime) {
+        let now = Instant::now();
+        for item in self.scripts.iter_mut() {
+            if let Some(action) = item.trigger.tick(now) {
+                item.handle(action, runtime)
+            }
+        }
+    }
+
+    /// 替换脚本列表: 定义未变的脚本保留运行状态, 其余运行中的脚本终止
+    fn reload(&mut self, mut list: ScriptList) {
+        let mut old: HashMap<Arc<String>, Script> = self.scripts.drain(..).map(|m| (m.title.clone(), m)).collect();
+
+        for item in list.scripts.iter_mut() {
+            match old.remove(&item.title) {
+                Some(prev) if prev.same(item) => {
+                    item.task = prev.task;
+                    item.trigger = prev.trigger;
+                    item.status = prev.status;
+                }
+                Some(mut prev) => prev.stop(),
+                None => {}
+            }
+        }
+        old.values_mut().for_each(Script::stop);
+
+        // 保留当前方案和屏蔽的按键, 由 refresh 按新的方案重新匹配
+        list.profile = self.profile.take();
+        list.swallowed = mem::take(&mut self.swallowed);
+        *self = list;
     }
 }
 
@@ -78,79 +423,561 @@ impl ScriptList {
 pub struct Script {
     pub title: Arc<String>,
     pub delay: u64,
+    /// Sleep 和延迟随机增减的毫秒数
+    pub jitter_ms: u64,
+    /// 鼠标位置随机偏移的像素数
+    pub jitter_px: f64,
     pub repeat: usize,
+    pub mode: Mode,
+    /// 屏蔽触发按键
+    pub swallow: bool,
     pub methods: Arc<Vec<Method>>,
+    pub vars: Arc<Vars>,
     pub task: Option<JoinHandle<()>>,
-    pub trigger: HashMap<KeyOrButton, bool>,
-    pub updater: UnboundedSender<Title>,
+    pub trigger: Matcher,
+    /// 生效的方案, 为空时对所有窗口生效
+    pub profiles: Vec<String>,
+    /// 所在的层
+    pub layer: Option<String>,
+    /// 最近一次运行的状态
+    pub status: Arc<Mutex<Status>>,
+    pub updater: UnboundedSender<Status>,
+    pub notifier: UnboundedSender<Notice>,
+}
+
+/// 修改脚本的运行状态并通知界面
+fn update(status: &Mutex<Status>, updater: &UnboundedSender<Status>, f: impl FnOnce(&mut Status)) {
+    let mut status = status.lock().unwrap();
+    f(&mut status);
+    let _ = updater.send(status.clone());
 }
 
 impl Script {
-    pub fn run(&mut self) {
+    /// 任务是否运行中
+    pub fn running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+
+    /// 当前的运行状态
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// 启动任务
+    pub fn start(&mut self, runtime: &Runtime) {
         let title = self.title.clone();
         let updater = self.updater.clone();
+        let status = self.status.clone();
 
-        if let Some(task) = self.task.take() {
-            if self.repeat == 0 || !task.is_finished() {
-                task.abort();
-                return updater.send((title.clone(), false)).unwrap();
+        update(&status, &updater, |s| {
+            *s = Status {
+                state: State::Running,
+                repeat: self.repeat,
+                methods: self.methods.len(),
+                // 与脚本中的等待使用同一个时钟
+                started: Some(tokio::time::Instant::now().into_std()),
+                ..Status::new(title.clone())
             }
-        }
-
-        let _ = updater.send((title.clone(), true));
+        });
 
-        let delay = self.delay;
         let repeat = self.repeat;
         let methods = self.methods.clone();
+        let notifier = self.notifier.clone();
+        let mut ctx = Context {
+            runtime: runtime.clone(),
+            delay: self.delay,
+            jitter_ms: self.jitter_ms,
+            jitter_px: self.jitter_px,
+            vars: Vars::clone(&self.vars),
+            texts: HashMap::new(),
+            index: 0,
+            notifier: notifier.clone(),
+        };
 
         let task = tokio::task::spawn(async move {
-            if repeat == 0 {
-                loop {
-                    run_method(&methods, delay).await;
+            let res = loop {
+                if repeat != 0 && ctx.index == repeat {
+                    break Ok(());
                 }
-            } else {
-                for _ in 0..repeat {
-                    run_method(&methods, delay).await;
+                if ctx.index > 0 {
+                    update(&status, &updater, |s| {
+                        s.state = State::Looping;
+                        s.iteration = ctx.index;
+                    });
                 }
-                let _ = updater.send((title, false));
+                match run_script(&methods, &mut ctx, &status).await {
+                    Ok(Flow::Break) => break Ok(()),
+                    Err(err) => break Err(err),
+                    Ok(_) => ctx.index += 1,
+                }
+                // 同 While, 避免没有等待的循环独占线程且无法终止
+                yield_now().await;
+            };
+            if let Err(err) = &res {
+                let _ = notifier.send(Notice::Message(format!("{title} 执行失败: {err}")));
             }
+            update(&status, &updater, |s| {
+                s.state = State::Idle;
+                s.iteration = ctx.index;
+                s.error = res.err().map(Arc::new);
+            });
         });
 
         self.task = Some(task);
     }
 
-    pub fn down(&mut self, key: &KeyOrButton) {
-        if let Some(k) = self.trigger.get_mut(key) {
-            *k = true;
+    /// 终止运行中的任务
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if !task.is_finished() {
+                task.abort();
+                update(&self.status, &self.updater, |s| s.state = State::Idle);
+            }
+        }
+    }
+
+    /// 脚本定义是否相同
+    fn same(&self, other: &Script) -> bool {
+        self.title == other.title
+            && self.delay == other.delay
+            && self.jitter_ms == other.jitter_ms
+            && self.jitter_px == other.jitter_px
+            && self.repeat == other.repeat
+            && self.mode == other.mode
+            && self.swallow == other.swallow
+            && self.methods == other.methods
+            && self.vars == other.vars
+            && self.trigger.trigger == other.trigger.trigger
+            && self.profiles == other.profiles
+            && self.layer == other.layer
+    }
+
+    /// 在当前方案和最上方的层下是否生效
+    fn active(&self, profile: Option<&String>, layer: Option<&String>) -> bool {
+        (self.profiles.is_empty() || profile.is_some_and(|p| self.profiles.contains(p)))
+            && (self.layer.is_none() || self.layer.as_ref() == layer)
+    }
+
+    /// 处理触发状态的变化
+    fn handle(&mut self, action: Action, runtime: &Runtime) {
+        match action {
+            Action::Pressed => self.pressed(runtime),
+            Action::Released => self.released(runtime),
+        }
+    }
+
+    /// 触发条件达成
+    fn pressed(&mut self, runtime: &Runtime) {
+        match self.mode {
+            Mode::Press | Mode::Hold if !self.running() => self.start(runtime),
+            Mode::Toggle if self.running() => self.stop(),
+            Mode::Toggle => self.start(runtime),
+            Mode::Restart => {
+                self.stop();
+                self.start(runtime)
+            }
+            _ => {}
+        }
+    }
+
+    /// 触发条件达成后松开了最后一步的按键
+    fn released(&mut self, runtime: &Runtime) {
+        match self.mode {
+            Mode::Release if !self.running() => self.start(runtime),
+            Mode::Hold => self.stop(),
+            _ => {}
+        }
+    }
+}
+
+/// 脚本运行共享的环境
+#[derive(Clone)]
+pub struct Runtime {
+    /// 键鼠输入后端
+    pub backend: Arc<dyn InputBackend>,
+    /// 截图来源
+    pub screen: Arc<dyn ScreenCapture>,
+    /// 焦点窗口来源
+    pub focus: Arc<dyn FocusProvider>,
+    /// 剪贴板
+    pub clipboard: Arc<dyn Clipboard>,
+    /// 当前按住的按键
+    pub held: Arc<Mutex<HashSet<KeyOrButton>>>,
+    /// 当前的层, 最后一个位于最上方
+    pub layers: Arc<Mutex<Vec<String>>>,
+    /// 鼠标最后的位置, 逐步移动时作为起点
+    pub cursor: Arc<Mutex<(f64, f64)>>,
+    /// 随机数
+    pub rng: Arc<Mutex<Rng>>,
+}
+
+impl Runtime {
+    pub fn new(backend: Arc<dyn InputBackend>) -> Self {
+        Self {
+            backend,
+            screen: Arc::new(DefaultScreen::default()),
+            focus: Arc::new(DefaultFocus::default()),
+            clipboard: Arc::new(DefaultClipboard::default()),
+            held: Default::default(),
+            layers: Default::default(),
+            cursor: Default::default(),
+            rng: Default::default(),
+        }
+    }
+
+    /// 替换截图来源, 如使用固定图片 [`ImageScreen`](screen::ImageScreen)
+    pub fn with_screen(mut self, screen: Arc<dyn ScreenCapture>) -> Self {
+        self.screen = screen;
+        self
+    }
+
+    /// 替换焦点窗口来源, 如 [`MockFocus`](focus::MockFocus)
+    pub fn with_focus(mut self, focus: Arc<dyn FocusProvider>) -> Self {
+        self.focus = focus;
+        self
+    }
+
+    /// 替换剪贴板, 如 [`MemoryClipboard`](clipboard::MemoryClipboard)
+    pub fn with_clipboard(mut self, clipboard: Arc<dyn Clipboard>) -> Self {
+        self.clipboard = clipboard;
+        self
+    }
+}
+
+/// 单次运行脚本的上下文
+pub struct Context {
+    pub runtime: Runtime,
+    pub delay: u64,
+    pub jitter_ms: u64,
+    pub jitter_px: f64,
+    pub vars: Vars,
+    /// 文本变量, 如读取的剪贴板
+    pub texts: HashMap<String, String>,
+    /// 当前循环的次数, 最外层为脚本的 repeat
+    pub index: usize,
+    pub notifier: UnboundedSender<Notice>,
+}
+
+impl Context {
+    /// 随机增减 jitter_ms 毫秒, 不小于 0
+    pub fn jitter(&self, ms: f64) -> Duration {
+        let ms = match self.jitter_ms {
+            0 => ms,
+            n => ms + self.runtime.rng.lock().unwrap().range(-(n as f64), n as f64),
+        };
+        Duration::from_secs_f64(ms.max(0.0) / 1000.0)
+    }
 
-            if self.trigger.values().all(|&flag| flag) {
-                self.run()
+    /// 替换文字中的 `${name}` 为文本变量或数字变量的值
+    pub fn format(&self, text: &str) -> Result<String, String> {
+        let mut res = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("${") {
+            res.push_str(&rest[..start]);
+            let end = start
+                + rest[start..]
+                    .find('}')
+                    .ok_or_else(|| format!("{text:?} 中的 ${{ 没有闭合"))?;
+            let name = &rest[start + 2..end];
+            match (self.texts.get(name), self.vars.get(name)) {
+                (Some(s), _) => res.push_str(s),
+                (None, Some(n)) => res.push_str(&n.to_string()),
+                (None, None) => return Err(format!("变量 {name:?} 未定义")),
             }
+            rest = &rest[end + 1..];
         }
+        res.push_str(rest);
+        Ok(res)
     }
 
-    pub fn up(&mut self, key: &KeyOrButton) {
-        if let Some(k) = self.trigger.get_mut(key) {
-            *k = false;
+    /// 位置随机偏移 jitter_px 像素
+    pub fn scatter(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        if self.jitter_px <= 0.0 {
+            return (x, y);
         }
+        let mut rng = self.runtime.rng.lock().unwrap();
+        let px = self.jitter_px;
+        (x + rng.range(-px, px), y + rng.range(-px, px))
+    }
+}
+
+impl Scope for Context {
+    fn var(&self, name: &str) -> Option<f64> {
+        self.vars.get(name).copied()
+    }
+
+    fn held(&self, key: &KeyOrButton) -> bool {
+        self.runtime.held.lock().unwrap().contains(key)
+    }
+
+    fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// 方法执行后的流程
+enum Flow {
+    Next,
+    Break,
+    Continue,
+}
+
+/// 模拟事件
+async fn simulate(event_type: &EventType, ctx: &Context) {
+    if let Err(err) = ctx.runtime.backend.simulate(event_type) {
+        let _ = ctx
+            .notifier
+            .send(Notice::Message(format!("事件 {event_type:?} 执行失败: {err}")));
     }
+    if let EventType::MouseMove { x, y } = *event_type {
+        *ctx.runtime.cursor.lock().unwrap() = (x, y);
+        sleep(Duration::from_micros(100)).await;
+    } else {
+        sleep(ctx.jitter(ctx.delay as f64)).await;
+    };
+}
+
+/// 输入文字, interval 为每个字符之间额外的毫秒数
+///
+/// 没有间隔时连续的键盘上没有的字符一起输入, 共用一个连接
+async fn type_text(text: &str, interval: f64, ctx: &Context) -> Result<(), String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if i > 0 && interval > 0.0 {
+            sleep(ctx.jitter(interval)).await;
+        }
+        if let Some((key, shift)) = typing::key(chars[i]) {
+            type_key(key, shift, ctx).await;
+            i += 1;
+            continue;
+        }
+        let len = match interval > 0.0 {
+            true => 1,
+            false => chars[i..].iter().take_while(|ch| typing::key(**ch).is_none()).count(),
+        };
+        type_unicode(chars[i..i + len].iter().collect(), ctx).await?;
+        i += len;
+    }
+    Ok(())
+}
+
+/// 输入键盘上没有的字符, 后端不支持时通过剪贴板粘贴
+async fn type_unicode(text: String, ctx: &Context) -> Result<(), String> {
+    let (backend, chars) = (ctx.runtime.backend.clone(), text.clone());
+    let res = spawn_blocking(move || backend.unicode(&chars))
+        .await
+        .map_err(|err| err.to_string())?;
+    match res {
+        Some(res) => res?,
+        None => paste_text(text, ctx).await?,
+    }
+    sleep(ctx.jitter(ctx.delay as f64)).await;
+    Ok(())
+}
+
+/// 写入剪贴板并粘贴, 之后还原剪贴板
+async fn paste_text(text: String, ctx: &Context) -> Result<(), String> {
+    let clipboard = ctx.runtime.clipboard.clone();
+    let old = spawn_blocking(move || {
+        let old = clipboard.get().ok();
+        clipboard.set(&text).map(|_| old)
+    })
+    .await
+    .map_err(|err| err.to_string())??;
+
+    simulate(&EventType::KeyPress(clipboard::PASTE), ctx).await;
+    simulate(&EventType::KeyPress(Key::KeyV), ctx).await;
+    simulate(&EventType::KeyRelease(Key::KeyV), ctx).await;
+    simulate(&EventType::KeyRelease(clipboard::PASTE), ctx).await;
+
+    // 等窗口读取剪贴板后再还原
+    let Some(old) = old else { return Ok(()) };
+    sleep(Duration::from_millis(100)).await;
+    let clipboard = ctx.runtime.clipboard.clone();
+    spawn_blocking(move || clipboard.set(&old))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+/// 点击单个按键, shift 为 true 时同时按住 ShiftLeft
+async fn type_key(key: Key, shift: bool, ctx: &Context) {
+    if shift {
+        simulate(&EventType::KeyPress(Key::ShiftLeft), ctx).await;
+    }
+    simulate(&EventType::KeyPress(key), ctx).await;
+    simulate(&EventType::KeyRelease(key), ctx).await;
+    if shift {
+        simulate(&EventType::KeyRelease(Key::ShiftLeft), ctx).await;
+    }
+}
+
+/// 在阻塞线程中截图判断像素颜色, 同 FindImage
+async fn test_pixel(pixel: &Pixel, ctx: &Context) -> Result<bool, String> {
+    let point = pixel.point(ctx)?;
+    let (pixel, screen) = (pixel.clone(), ctx.runtime.screen.clone());
+    spawn_blocking(move || pixel.test(&*screen, point))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+/// 逐个执行最外层的方法, 并更新当前方法的序号
+///
+/// 序号只写入共享的状态, 不发送给界面, 由界面定时读取
+async fn run_script(methods: &[Method], ctx: &mut Context, status: &Mutex<Status>) -> Result<Flow, String> {
+    for (i, method) in methods.iter().enumerate() {
+        status.lock().unwrap().method = i;
+        match run_method(slice::from_ref(method), ctx).await? {
+            Flow::Next => {}
+            flow => return Ok(flow),
+        }
+    }
+    Ok(Flow::Next)
 }
 
 /// 运行脚本方法
-async fn run_method(methods: &Arc<Vec<Method>>, delay: u64) {
-    for method in methods.iter() {
-        match method {
-            Method::Event(event_type) => {
-                if let Err(err) = simulate(event_type) {
-                    println!("事件 {event_type:?} 执行失败: {err}");
+fn run_method<'a>(
+    methods: &'a [Method],
+    ctx: &'a mut Context,
+) -> Pin<Box<dyn Future<Output = Result<Flow, String>> + Send + 'a>> {
+    Box::pin(async move {
+        for method in methods.iter() {
+            match method {
+                Method::Event(event_type) => simulate(event_type, ctx).await,
+                Method::Move(x, y, None) => {
+                    let (x, y) = ctx.scatter((x.eval(ctx)?, y.eval(ctx)?));
+                    simulate(&EventType::MouseMove { x, y }, ctx).await
                 }
-                if let EventType::MouseMove { .. } = event_type {
-                    sleep(Duration::from_micros(100)).await;
-                } else {
-                    sleep(Duration::from_millis(delay)).await;
-                };
+                Method::Move(x, y, Some(movement)) => {
+                    let to = ctx.scatter((x.eval(ctx)?, y.eval(ctx)?));
+                    let from = *ctx.runtime.cursor.lock().unwrap();
+                    let points = movement.points(from, to, &*ctx, &mut ctx.runtime.rng.lock().unwrap())?;
+                    let start = Instant::now();
+                    for (i, (x, y)) in points.into_iter().enumerate() {
+                        sleep_until((start + STEP * (i as u32 + 1)).into()).await;
+                        simulate(&EventType::MouseMove { x, y }, ctx).await
+                    }
+                }
+                Method::Path(points) => {
+                    // 按开始的时间计算每个点的时间, 避免误差累积
+                    let start = Instant::now();
+                    let mut elapsed = 0;
+                    for (ms, (x, y)) in points {
+                        elapsed += ms;
+                        sleep_until((start + Duration::from_millis(elapsed)).into()).await;
+                        let (x, y) = (x.eval(ctx)?, y.eval(ctx)?);
+                        simulate(&EventType::MouseMove { x, y }, ctx).await
+                    }
+                }
+                Method::Type { text, interval } => {
+                    let text = ctx.format(text)?;
+                    let interval = interval.eval(ctx)?;
+                    type_text(&text, interval, ctx).await?
+                }
+                Method::Scroll(delta_x, delta_y) => {
+                    let delta_x = delta_x.eval(ctx)?.round() as i64;
+                    let delta_y = delta_y.eval(ctx)?.round() as i64;
+                    simulate(&EventType::Wheel { delta_x, delta_y }, ctx).await
+                }
+                Method::Block { repeat, sleep: interval, methods } => {
+                    let repeat = repeat.eval(ctx)?.max(0.0) as usize;
+                    let outer = ctx.index;
+                    for i in 0..repeat {
+                        if i > 0 {
+                            sleep(Duration::from_millis(interval.eval(ctx)?.max(0.0) as u64)).await;
+                        }
+                        ctx.index = i;
+                        if let Flow::Break = run_method(methods, ctx).await? {
+                            break;
+                        }
+                    }
+                    ctx.index = outer;
+                }
+                Method::If { cond, then, r#else } => {
+                    let branch = if cond.test(ctx)? { then } else { r#else };
+                    match run_method(branch, ctx).await? {
+                        Flow::Next => {}
+                        flow => return Ok(flow),
+                    }
+                }
+                Method::While { cond, methods } => {
+                    let outer = ctx.index;
+                    ctx.index = 0;
+                    while cond.test(ctx)? {
+                        if let Flow::Break = run_method(methods, ctx).await? {
+                            break;
+                        }
+                        ctx.index += 1;
+                        // 避免空循环独占线程且无法终止
+                        yield_now().await;
+                    }
+                    ctx.index = outer;
+                }
+                Method::WaitPixel { pixel, timeout } => {
+                    let timeout = match timeout {
+                        Some(n) => Some(Instant::now() + Duration::from_millis(n.eval(ctx)?.max(0.0) as u64)),
+                        None => None,
+                    };
+                    while !test_pixel(pixel, ctx).await? {
+                        if timeout.is_some_and(|t| Instant::now() >= t) {
+                            return Err(format!("等待像素颜色 {:?} 超时", pixel.color));
+                        }
+                        sleep(Duration::from_millis(50)).await;
+                    }
+                }
+                Method::IfPixel { pixel, then, r#else } => {
+                    let branch = if test_pixel(pixel, ctx).await? { then } else { r#else };
+                    match run_method(branch, ctx).await? {
+                        Flow::Next => {}
+                        flow => return Ok(flow),
+                    }
+                }
+                Method::FindImage { template, var, required } => {
+                    let region = template.region(ctx)?;
+                    let (template, screen) = (template.clone(), ctx.runtime.screen.clone());
+                    let found = spawn_blocking(move || template.search(&*screen, region))
+                        .await
+                        .map_err(|err| err.to_string())??;
+                    match found {
+                        Some((x, y)) => {
+                            ctx.vars.insert(var.clone(), 1.0);
+                            ctx.vars.insert(format!("{var}_x"), x);
+                            ctx.vars.insert(format!("{var}_y"), y);
+                        }
+                        None if *required => return Err("没有在屏幕上找到图片".into()),
+                        None => {
+                            ctx.vars.insert(var.clone(), 0.0);
+                        }
+                    }
+                }
+                Method::Break => return Ok(Flow::Break),
+                Method::Continue => return Ok(Flow::Continue),
+                Method::Custom(c) => c.run(ctx).await?,
             }
-            Method::Custom(c) => c.run().await,
         }
+        Ok(Flow::Next)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress() {
+        let status = |repeat, iteration, method| Status {
+            repeat,
+            iteration,
+            method,
+            methods: 4,
+            ..Status::new(Arc::new("A".into()))
+        };
+        assert_eq!(status(0, 3, 1).progress(), None);
+        assert_eq!(status(2, 0, 0).progress(), Some(0.0));
+        assert_eq!(status(2, 0, 2).progress(), Some(0.25));
+        assert_eq!(status(2, 1, 2).progress(), Some(0.75));
+        assert_eq!(status(2, 2, 0).progress(), Some(1.0));
+        // 没有方法时按整轮计算
+        assert_eq!(Status { methods: 0, ..status(4, 1, 0) }.progress(), Some(0.25));
     }
 }
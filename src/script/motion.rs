@@ -0,0 +1,174 @@
+use std::{f64::consts::PI, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::script::{
+    expr::{Expr, Scope},
+    random::Rng,
+};
+
+/// 两个轨迹点之间的间隔
+pub const STEP: Duration = Duration::from_millis(10);
+
+/// 没有指定时长和速度时的时长(毫秒)
+const DEFAULT_DURATION: f64 = 200.0;
+
+/// 移动曲线
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Curve {
+    /// 匀速直线
+    Linear,
+    /// 先加速后减速的直线
+    #[default]
+    EaseInOut,
+    /// 随机弯曲的贝塞尔曲线, 先加速后减速
+    Bezier,
+}
+
+/// 逐步移动鼠标的方式
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movement {
+    pub curve: Curve,
+    /// 总毫秒数
+    pub duration: Option<Expr>,
+    /// 每秒移动的像素数, 没有 duration 时使用
+    pub speed: Option<Expr>,
+    /// 随机偏移的像素数
+    pub jitter: Option<Expr>,
+}
+
+impl Movement {
+    /// 从 from 移动到 to 的轨迹, 每隔 [`STEP`] 一个点, 最后一个点为 to
+    pub fn points(
+        &self,
+        from: (f64, f64),
+        to: (f64, f64),
+        scope: &impl Scope,
+        rng: &mut Rng,
+    ) -> Result<Vec<(f64, f64)>, String> {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let distance = dx.hypot(dy);
+        let duration = match (&self.duration, &self.speed) {
+            (Some(duration), _) => duration.eval(scope)?,
+            (None, Some(speed)) => distance / speed.eval(scope)? * 1000.0,
+            (None, None) => DEFAULT_DURATION,
+        };
+        let steps = (duration / STEP.as_millis() as f64).ceil();
+        let steps = if steps.is_finite() { steps.max(1.0) as usize } else { 1 };
+        let jitter = match (&self.jitter, self.curve) {
+            (Some(jitter), _) => jitter.eval(scope)?.max(0.0),
+            (None, Curve::Bezier) => distance / 5.0,
+            (None, _) => 0.0,
+        };
+
+        // 垂直于移动方向的单位向量, 控制点沿该方向偏移
+        let normal = if distance > 0.0 {
+            (-dy / distance, dx / distance)
+        } else {
+            (0.0, 0.0)
+        };
+        let mut control = |t: f64| {
+            let offset = rng.range(-jitter, jitter);
+            (from.0 + dx * t + normal.0 * offset, from.1 + dy * t + normal.1 * offset)
+        };
+        let (c1, c2) = (control(1.0 / 3.0), control(2.0 / 3.0));
+        // 直线整体偏移同一个随机位移, 中间最大, 两端为 0, 避免逐点的随机抖动
+        let offset = (rng.range(-jitter, jitter), rng.range(-jitter, jitter));
+
+        let mut points = Vec::with_capacity(steps);
+        for i in 1..steps {
+            let t = i as f64 / steps as f64;
+            let point = match self.curve {
+                Curve::Linear => (from.0 + dx * t, from.1 + dy * t),
+                Curve::EaseInOut => (from.0 + dx * ease(t), from.1 + dy * ease(t)),
+                Curve::Bezier => bezier(from, c1, c2, to, ease(t)),
+            };
+            // 贝塞尔曲线的随机性已体现在控制点上
+            let point = match self.curve {
+                Curve::Bezier => point,
+                _ => {
+                    let k = (PI * t).sin();
+                    (point.0 + offset.0 * k, point.1 + offset.1 * k)
+                }
+            };
+            points.push(point);
+        }
+        points.push(to);
+        Ok(points)
+    }
+}
+
+/// 缓入缓出
+fn ease(t: f64) -> f64 {
+    (1.0 - (PI * t).cos()) / 2.0
+}
+
+/// 三次贝塞尔曲线上的点
+fn bezier(p0: (f64, f64), p1: (f64, f64), p2: (f64, f64), p3: (f64, f64), t: f64) -> (f64, f64) {
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    (
+        a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+        a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{config::KeyOrButton, expr::Vars};
+
+    impl Scope for Vars {
+        fn var(&self, name: &str) -> Option<f64> {
+            self.get(name).copied()
+        }
+
+        fn held(&self, _: &KeyOrButton) -> bool {
+            false
+        }
+
+        fn index(&self) -> usize {
+            0
+        }
+    }
+
+    fn points(curve: Curve, jitter: f64, seed: u64) -> Vec<(f64, f64)> {
+        let movement = Movement {
+            curve,
+            duration: Some(Expr::Num(200.0)),
+            speed: None,
+            jitter: Some(Expr::Num(jitter)),
+        };
+        let mut rng = Rng::new(seed);
+        movement
+            .points((0.0, 0.0), (200.0, 0.0), &Vars::new(), &mut rng)
+            .unwrap()
+    }
+
+    #[test]
+    fn linear_without_jitter() {
+        let points = points(Curve::Linear, 0.0, 1);
+        assert_eq!(points.len(), 20);
+        assert_eq!(points[0], (10.0, 0.0));
+        assert_eq!(points[9], (100.0, 0.0));
+        assert_eq!(points[19], (200.0, 0.0));
+    }
+
+    #[test]
+    fn jitter_is_smooth() {
+        for curve in [Curve::Linear, Curve::EaseInOut] {
+            let points = points(curve, 20.0, 7);
+            assert_eq!(points.last(), Some(&(200.0, 0.0)));
+            // 偏移不超过 jitter, 相邻两点的偏移变化很小, 不会来回抖动
+            let offsets: Vec<f64> = points.iter().map(|p| p.1).collect();
+            assert!(offsets.iter().all(|y| y.abs() <= 20.0));
+            assert!(offsets.iter().any(|y| y.abs() > 0.5));
+            let turns = offsets
+                .windows(3)
+                .filter(|w| (w[1] - w[0]) * (w[2] - w[1]) < 0.0)
+                .count();
+            assert!(turns <= 1, "{curve:?} {offsets:?}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    f64::consts::PI,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::script::expr::{Expr, Scope};
+
+/// 伪随机数生成器(xorshift64*), 用于模拟人工操作的随机性, 不适用于加密
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// 相同的种子生成相同的序列
+    pub fn new(seed: u64) -> Self {
+        // 状态为 0 时 xorshift 只会生成 0
+        match seed ^ 0x9E37_79B9_7F4A_7C15 {
+            0 => Self(0x9E37_79B9_7F4A_7C15),
+            state => Self(state),
+        }
+    }
+
+    /// 以当前时间为种子
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Self::new(nanos as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// [0, 1) 之间的随机数
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// [min, max) 之间的随机数
+    pub fn range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.float()
+    }
+
+    /// 正态分布的随机数(Box-Muller)
+    pub fn gaussian(&mut self, mean: f64, std: f64) -> f64 {
+        let u = 1.0 - self.float();
+        let v = self.float();
+        mean + std * (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_time()
+    }
+}
+
+/// 随机取值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Random {
+    /// 固定值
+    Fixed(Expr),
+    /// 最小值和最大值之间均匀分布
+    Uniform(Expr, Expr),
+    /// 按平均值和标准差正态分布
+    Gaussian(Expr, Expr),
+}
+
+impl Random {
+    pub fn sample(&self, scope: &impl Scope, rng: &mut Rng) -> Result<f64, String> {
+        Ok(match self {
+            Random::Fixed(n) => n.eval(scope)?,
+            Random::Uniform(min, max) => rng.range(min.eval(scope)?, max.eval(scope)?),
+            Random::Gaussian(mean, std) => rng.gaussian(mean.eval(scope)?, std.eval(scope)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let sample = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..8).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(sample(42), sample(42));
+        assert_ne!(sample(42), sample(43));
+        // 会让 xorshift 状态为 0 的种子
+        assert!(sample(0x9E37_79B9_7F4A_7C15).iter().any(|n| *n != 0));
+    }
+
+    #[test]
+    fn range_and_gaussian() {
+        let mut rng = Rng::new(1);
+        assert!((0..1000)
+            .map(|_| rng.range(-2.0, 3.0))
+            .all(|n| (-2.0..3.0).contains(&n)));
+        let mean = (0..10000).map(|_| rng.gaussian(100.0, 15.0)).sum::<f64>() / 10000.0;
+        assert!((mean - 100.0).abs() < 1.0, "{mean}");
+    }
+}
@@ -0,0 +1,444 @@
+use std::{fs::File, path::Path, sync::Arc};
+
+use png::{ColorType, Decoder, Transformations};
+
+use crate::script::expr::{Expr, Scope};
+
+/// 颜色 (r, g, b)
+pub type Color = (u8, u8, u8);
+
+/// 屏幕区域
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// 与另一区域的交集
+    fn intersect(&self, other: &Region) -> Option<Region> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width as i32).min(other.x + other.width as i32);
+        let bottom = (self.y + self.height as i32).min(other.y + other.height as i32);
+        (right > x && bottom > y).then(|| Region { x, y, width: (right - x) as u32, height: (bottom - y) as u32 })
+    }
+}
+
+/// 截图
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// 按行排列的像素
+    pub pixels: Vec<Color>,
+}
+
+impl Frame {
+    /// 读取 png 图片
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let err = |err: &dyn std::fmt::Display| format!("读取图片 {path:?} 失败: {err}");
+
+        let file = File::open(path).map_err(|e| err(&e))?;
+        let mut decoder = Decoder::new(file);
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| err(&e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| err(&e))?;
+
+        let pixel = |p: &[u8]| match info.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => (p[0], p[0], p[0]),
+            _ => (p[0], p[1], p[2]),
+        };
+        let pixels = buf[..info.buffer_size()]
+            .chunks_exact(info.color_type.samples())
+            .map(pixel)
+            .collect();
+
+        Ok(Self { width: info.width, height: info.height, pixels })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.pixels.get((y * self.width + x) as usize).copied()
+    }
+
+    /// 查找模板图片, 返回相似度(0~1)不低于 threshold 的最佳匹配的左上角位置和相似度
+    pub fn find(&self, template: &Frame, threshold: f64) -> Option<(u32, u32, f64)> {
+        if template.pixels.is_empty() || template.width > self.width || template.height > self.height {
+            return None;
+        }
+        let total = (template.pixels.len() * 3 * 255) as f64;
+        let mut best: Option<(u32, u32, f64)> = None;
+
+        for y in 0..=self.height - template.height {
+            for x in 0..=self.width - template.width {
+                // 差异超过当前最佳结果时提前结束
+                let min = best.map_or(threshold, |(_, _, score)| score.max(threshold));
+                let limit = ((1.0 - min) * total) as u64;
+                let mut diff = 0;
+                for ty in 0..template.height {
+                    let row = ((y + ty) * self.width + x) as usize;
+                    let pixels = &self.pixels[row..row + template.width as usize];
+                    let start = (ty * template.width) as usize;
+                    let expected = &template.pixels[start..start + template.width as usize];
+                    diff += pixels
+                        .iter()
+                        .zip(expected)
+                        .map(|(a, b)| a.0.abs_diff(b.0) as u64 + a.1.abs_diff(b.1) as u64 + a.2.abs_diff(b.2) as u64)
+                        .sum::<u64>();
+                    if diff > limit {
+                        break;
+                    }
+                }
+                if diff <= limit {
+                    let score = 1.0 - diff as f64 / total;
+                    if best.is_none_or(|(_, _, best)| score > best) {
+                        best = Some((x, y, score));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// 截取区域
+    pub fn crop(&self, region: Region) -> Option<Frame> {
+        let region = region.intersect(&Region { x: 0, y: 0, width: self.width, height: self.height })?;
+        let pixels = (region.y..region.y + region.height as i32)
+            .flat_map(|y| (region.x..region.x + region.width as i32).map(move |x| (x, y)))
+            .filter_map(|(x, y)| self.pixel(x as u32, y as u32))
+            .collect();
+        Some(Frame { width: region.width, height: region.height, pixels })
+    }
+}
+
+/// 颜色是否在容差内
+pub fn color_match(a: Color, b: Color, tolerance: u8) -> bool {
+    a.0.abs_diff(b.0) <= tolerance && a.1.abs_diff(b.1) <= tolerance && a.2.abs_diff(b.2) <= tolerance
+}
+
+/// 截图来源
+pub trait ScreenCapture: Send + Sync {
+    /// 截取屏幕区域, None 为整个屏幕
+    fn capture(&self, region: Option<Region>) -> Result<Frame, String>;
+}
+
+/// 使用固定的图片作为屏幕
+#[derive(Debug, Clone)]
+pub struct ImageScreen(pub Frame);
+
+impl ImageScreen {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Frame::open(path).map(Self)
+    }
+}
+
+impl ScreenCapture for ImageScreen {
+    fn capture(&self, region: Option<Region>) -> Result<Frame, String> {
+        match region {
+            None => Ok(self.0.clone()),
+            Some(region) => self.0.crop(region).ok_or_else(|| format!("区域 {region:?} 超出屏幕")),
+        }
+    }
+}
+
+/// X11 屏幕, 复用同一个连接
+#[cfg(target_os = "linux")]
+#[derive(Debug, Default)]
+pub struct X11Screen {
+    display: crate::script::xconn::XConnection,
+}
+
+#[cfg(target_os = "linux")]
+impl ScreenCapture for X11Screen {
+    fn capture(&self, region: Option<Region>) -> Result<Frame, String> {
+        use std::mem;
+
+        use x11::xlib;
+
+        self.display.with(|display| unsafe {
+            let root = xlib::XDefaultRootWindow(display);
+            let mut attrs: xlib::XWindowAttributes = mem::zeroed();
+            xlib::XGetWindowAttributes(display, root, &mut attrs);
+
+            // 超出屏幕的区域会触发 X11 错误, 先裁剪到屏幕内
+            let screen = Region {
+                x: 0,
+                y: 0,
+                width: attrs.width as u32,
+                height: attrs.height as u32,
+            };
+            let Some(region) = region.map_or(Some(screen), |r| r.intersect(&screen)) else {
+                return Err(format!("区域 {region:?} 超出屏幕"));
+            };
+
+            let image = xlib::XGetImage(
+                display,
+                root,
+                region.x,
+                region.y,
+                region.width,
+                region.height,
+                xlib::XAllPlanes(),
+                xlib::ZPixmap,
+            );
+            if image.is_null() {
+                return Err("截图失败".into());
+            }
+
+            let (red, green, blue) = ((*image).red_mask, (*image).green_mask, (*image).blue_mask);
+            let mut pixels = Vec::with_capacity((region.width * region.height) as usize);
+            for y in 0..region.height as i32 {
+                for x in 0..region.width as i32 {
+                    let pixel = xlib::XGetPixel(image, x, y);
+                    let channel = |mask| channel(pixel, mask);
+                    pixels.push((channel(red), channel(green), channel(blue)));
+                }
+            }
+
+            xlib::XDestroyImage(image);
+            Ok(Frame { width: region.width, height: region.height, pixels })
+        })
+    }
+}
+
+/// 按掩码取出像素的一个通道, 不是 8 位的通道按比例换算到 0-255, 没有该通道时为 0
+#[cfg(any(target_os = "linux", test))]
+fn channel(pixel: std::os::raw::c_ulong, mask: std::os::raw::c_ulong) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    let value = (pixel & mask) >> shift;
+    match max {
+        0xff => value as u8,
+        _ => (value as f64 * 255.0 / max as f64).round() as u8,
+    }
+}
+
+/// 不支持截图的平台
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UnsupportedScreen;
+
+#[cfg(not(target_os = "linux"))]
+impl ScreenCapture for UnsupportedScreen {
+    fn capture(&self, _region: Option<Region>) -> Result<Frame, String> {
+        Err("当前平台暂不支持截图".into())
+    }
+}
+
+/// 当前平台的屏幕
+#[cfg(target_os = "linux")]
+pub type DefaultScreen = X11Screen;
+
+/// 当前平台的屏幕
+#[cfg(not(target_os = "linux"))]
+pub type DefaultScreen = UnsupportedScreen;
+
+/// 像素颜色条件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pixel {
+    pub x: Expr,
+    pub y: Expr,
+    pub color: Color,
+    /// 每个通道允许的误差
+    pub tolerance: u8,
+}
+
+impl Pixel {
+    /// 计算屏幕位置
+    pub fn point(&self, scope: &impl Scope) -> Result<(i32, i32), String> {
+        Ok((self.x.eval(scope)?.round() as i32, self.y.eval(scope)?.round() as i32))
+    }
+
+    /// 截图判断指定位置的颜色, 会阻塞当前线程
+    pub fn test(&self, screen: &dyn ScreenCapture, (x, y): (i32, i32)) -> Result<bool, String> {
+        let frame = screen.capture(Some(Region { x, y, width: 1, height: 1 }))?;
+        Ok(frame
+            .pixel(0, 0)
+            .is_some_and(|c| color_match(c, self.color, self.tolerance)))
+    }
+}
+
+/// 图片查找条件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub image: Arc<Frame>,
+    /// 最低相似度(0~1)
+    pub threshold: f64,
+    /// 查找区域 [x, y, 宽, 高], 已换算为屏幕位置
+    pub region: Option<[Expr; 4]>,
+    /// 缩放和偏移, 用于把结果换算回配置中的位置
+    pub scaling: f64,
+    pub offset: (f64, f64),
+}
+
+impl Template {
+    /// 计算查找区域
+    pub fn region(&self, scope: &impl Scope) -> Result<Option<Region>, String> {
+        let Some([x, y, width, height]) = &self.region else {
+            return Ok(None);
+        };
+        Ok(Some(Region {
+            x: x.eval(scope)?.round() as i32,
+            y: y.eval(scope)?.round() as i32,
+            width: width.eval(scope)?.max(0.0).round() as u32,
+            height: height.eval(scope)?.max(0.0).round() as u32,
+        }))
+    }
+
+    /// 截图并查找, 返回匹配的中心位置(配置中的坐标)
+    pub fn search(&self, screen: &dyn ScreenCapture, region: Option<Region>) -> Result<Option<(f64, f64)>, String> {
+        let frame = screen.capture(region)?;
+        let (left, top) = region.map_or((0, 0), |r| (r.x.max(0), r.y.max(0)));
+        Ok(frame.find(&self.image, self.threshold).map(|(x, y, _)| {
+            let x = (left + x as i32) as f64 + self.image.width as f64 / 2.0;
+            let y = (top + y as i32) as f64 + self.image.height as f64 / 2.0;
+            (x * self.scaling - self.offset.0, y * self.scaling - self.offset.1)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::BufWriter, path::PathBuf};
+
+    use png::Encoder;
+
+    use super::*;
+
+    /// 写入 rgb 的 png 图片, 返回路径
+    fn write_png(name: &str, frame: &Frame) -> PathBuf {
+        let path = env::temp_dir().join(format!("kmm-{}-{name}.png", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let mut encoder = Encoder::new(BufWriter::new(file), frame.width, frame.height);
+        encoder.set_color(ColorType::Rgb);
+        let data: Vec<u8> = frame.pixels.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+        encoder.write_header().unwrap().write_image_data(&data).unwrap();
+        path
+    }
+
+    /// 每个像素的颜色由位置决定
+    fn gradient(width: u32, height: u32) -> Frame {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| ((x * 10) as u8, (y * 10) as u8, 128)))
+            .collect();
+        Frame { width, height, pixels }
+    }
+
+    #[test]
+    fn channel_scales_masks() {
+        // 24 位色
+        assert_eq!(channel(0x12_34_56, 0xff_00_00), 0x12);
+        assert_eq!(channel(0x12_34_56, 0xff), 0x56);
+        // 16 位色 (5-6-5)
+        assert_eq!(channel(0xffff, 0xf800), 255);
+        assert_eq!(channel(0x0400, 0x07e0), 130);
+        assert_eq!(channel(0x001f, 0xf800), 0);
+        // 30 位色的 10 位通道
+        assert_eq!(channel(0x3ff << 20, 0x3ff << 20), 255);
+        assert_eq!(channel(0x200, 0x3ff), 128);
+        // 没有该通道
+        assert_eq!(channel(0xffff_ffff, 0), 0);
+    }
+
+    #[test]
+    fn image_screen_from_png() {
+        let frame = gradient(8, 6);
+        let path = write_png("screen", &frame);
+        let screen = ImageScreen::open(&path);
+        fs::remove_file(&path).unwrap();
+        let screen = screen.unwrap();
+
+        assert_eq!(screen.capture(None).unwrap(), frame);
+        let region = screen
+            .capture(Some(Region { x: 6, y: 4, width: 4, height: 4 }))
+            .unwrap();
+        assert_eq!((region.width, region.height), (2, 2));
+        assert_eq!(
+            region.pixels,
+            vec![(60, 40, 128), (70, 40, 128), (60, 50, 128), (70, 50, 128)]
+        );
+        assert!(screen
+            .capture(Some(Region { x: 8, y: 0, width: 1, height: 1 }))
+            .is_err());
+        assert!(ImageScreen::open(env::temp_dir().join("kmm-missing.png")).is_err());
+    }
+
+    #[test]
+    fn pixel_test_with_tolerance() {
+        struct NoScope;
+        impl Scope for NoScope {
+            fn var(&self, _: &str) -> Option<f64> {
+                None
+            }
+            fn held(&self, _: &crate::script::config::KeyOrButton) -> bool {
+                false
+            }
+            fn index(&self) -> usize {
+                0
+            }
+        }
+
+        let screen = ImageScreen(gradient(8, 6));
+        let pixel = |color, tolerance| Pixel {
+            x: Expr::parse("1 + 2").unwrap(),
+            y: Expr::parse("2").unwrap(),
+            color,
+            tolerance,
+        };
+        let point = pixel((0, 0, 0), 0).point(&NoScope).unwrap();
+        assert_eq!(point, (3, 2));
+        assert!(pixel((30, 20, 128), 0).test(&screen, point).unwrap());
+        assert!(!pixel((35, 20, 128), 0).test(&screen, point).unwrap());
+        assert!(pixel((35, 20, 128), 5).test(&screen, point).unwrap());
+        assert!(pixel((0, 0, 0), 0).test(&screen, (20, 20)).is_err());
+    }
+
+    #[test]
+    fn find_template() {
+        let screen = gradient(8, 6);
+        let template = screen.crop(Region { x: 2, y: 3, width: 3, height: 2 }).unwrap();
+        let (x, y, score) = screen.find(&template, 0.99).unwrap();
+        assert_eq!((x, y, score), (2, 3, 1.0));
+
+        // 稍有差异时相似度降低, 低于阈值时找不到
+        let mut similar = template.clone();
+        similar.pixels[0].2 = 0;
+        let (x, y, score) = screen.find(&similar, 0.9).unwrap();
+        assert_eq!((x, y), (2, 3));
+        assert!(score < 1.0);
+        assert_eq!(screen.find(&similar, 0.99), None);
+
+        // 比屏幕大的模板和空模板
+        assert_eq!(screen.find(&gradient(9, 1), 0.0), None);
+        assert_eq!(screen.find(&gradient(0, 0), 0.0), None);
+    }
+
+    #[test]
+    fn search_scales_back_to_config_position() {
+        let screen = ImageScreen(gradient(8, 6));
+        let image = screen.0.crop(Region { x: 4, y: 2, width: 2, height: 2 }).unwrap();
+        let template = Template {
+            image: Arc::new(image),
+            threshold: 1.0,
+            region: None,
+            scaling: 2.0,
+            offset: (10.0, 0.0),
+        };
+        // 屏幕上的中心为 (5, 3)
+        assert_eq!(template.search(&screen, None), Ok(Some((0.0, 6.0))));
+        let region = Region { x: 3, y: 1, width: 4, height: 4 };
+        assert_eq!(template.search(&screen, Some(region)), Ok(Some((0.0, 6.0))));
+        let region = Region { x: 0, y: 0, width: 4, height: 4 };
+        assert_eq!(template.search(&screen, Some(region)), Ok(None));
+    }
+}
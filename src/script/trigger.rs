@@ -0,0 +1,335 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    time::{Duration, Instant},
+};
+
+use crate::script::config::KeyOrButton;
+
+/// 触发条件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    /// 按顺序完成的每一步, 每一步的按键需要同时按下
+    pub steps: Vec<Vec<KeyOrButton>>,
+    /// 最后一步需要按住的时间
+    pub long_press: Option<Duration>,
+    /// 每一步之间的最大间隔
+    pub timeout: Duration,
+}
+
+impl Trigger {
+    /// 涉及的所有按键
+    pub fn keys(&self) -> impl Iterator<Item = &KeyOrButton> {
+        self.steps.iter().flatten()
+    }
+
+    fn last(&self) -> &[KeyOrButton] {
+        self.steps.last().map_or(&[], |step| step.as_slice())
+    }
+
+    /// 要屏蔽的按键: 最后一步中最后一个不是修饰键的按键, 都是修饰键时取最后一个
+    pub fn swallow(&self) -> Option<Swallow> {
+        let last = self.last();
+        let index = last
+            .iter()
+            .rposition(|k| !k.is_modifier())
+            .or(last.len().checked_sub(1))?;
+        let mut modifiers = last.to_vec();
+        let key = modifiers.remove(index);
+        Some(Swallow { key, modifiers })
+    }
+}
+
+/// 屏蔽的按键: 只在按住 modifiers 时屏蔽 key 的按下和松开
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Swallow {
+    pub key: KeyOrButton,
+    pub modifiers: Vec<KeyOrButton>,
+}
+
+/// 如 `ControlLeft+KeyK`
+impl Display for Swallow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for key in self.modifiers.iter() {
+            write_key(f, key)?;
+            f.write_str("+")?;
+        }
+        write_key(f, &self.key)
+    }
+}
+
+fn write_key(f: &mut Formatter<'_>, key: &KeyOrButton) -> fmt::Result {
+    match key {
+        KeyOrButton::Key(key) => write!(f, "{key:?}"),
+        KeyOrButton::Mouse(button) => write!(f, "Mouse{button:?}"),
+    }
+}
+
+/// 每一步的按键用 + 连接, 步骤之间用 , 分隔, 如 `ControlLeft+KeyA, KeyB (长按 500ms)`
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            for (j, key) in step.iter().enumerate() {
+                if j > 0 {
+                    f.write_str("+")?;
+                }
+                write_key(f, key)?;
+            }
+        }
+        match self.long_press {
+            Some(duration) => write!(f, " (长按 {}ms)", duration.as_millis()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 触发状态的变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// 触发条件达成
+    Pressed,
+    /// 达成后松开了最后一步的按键
+    Released,
+}
+
+/// 按键序列的状态机
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub trigger: Trigger,
+    /// 已完成的步数
+    pos: usize,
+    /// 上一步完成的时间
+    last: Instant,
+    /// 最后一步按下的时间, 等待长按
+    pending: Option<Instant>,
+    /// 已触发, 等待松开
+    active: bool,
+}
+
+impl Matcher {
+    pub fn new(trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            pos: 0,
+            last: Instant::now(),
+            pending: None,
+            active: false,
+        }
+    }
+
+    /// 按键按下, held 为当前按住的所有按键
+    pub fn down(&mut self, key: &KeyOrButton, held: &HashSet<KeyOrButton>, now: Instant) -> Option<Action> {
+        if self.active || self.pending.is_some() {
+            return None;
+        }
+        if self.pos > 0 && now.duration_since(self.last) > self.trigger.timeout {
+            self.pos = 0;
+        }
+
+        let step = &self.trigger.steps[self.pos];
+        if !step.contains(key) {
+            // 按错时从头开始匹配
+            if self.pos > 0 {
+                self.pos = 0;
+                return self.down(key, held, now);
+            }
+            return None;
+        }
+        if !step.iter().all(|k| held.contains(k)) {
+            return None;
+        }
+
+        self.last = now;
+        self.pos += 1;
+        if self.pos < self.trigger.steps.len() {
+            return None;
+        }
+        self.pos = 0;
+        match self.trigger.long_press {
+            Some(_) => {
+                self.pending = Some(now);
+                None
+            }
+            None => {
+                self.active = true;
+                Some(Action::Pressed)
+            }
+        }
+    }
+
+    /// 按键松开
+    pub fn up(&mut self, key: &KeyOrButton) -> Option<Action> {
+        if !self.trigger.last().contains(key) {
+            return None;
+        }
+        // 长按时间不够
+        self.pending = None;
+        match self.active {
+            true => {
+                self.active = false;
+                Some(Action::Released)
+            }
+            false => None,
+        }
+    }
+
+    /// 检查长按是否到时间
+    pub fn tick(&mut self, now: Instant) -> Option<Action> {
+        let deadline = self.deadline()?;
+        if now < deadline {
+            return None;
+        }
+        self.pending = None;
+        self.active = true;
+        Some(Action::Pressed)
+    }
+
+    /// 下次需要检查长按的时间
+    pub fn deadline(&self) -> Option<Instant> {
+        Some(self.pending? + self.trigger.long_press?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdev::Key;
+
+    use super::*;
+
+    const K: fn(Key) -> KeyOrButton = KeyOrButton::Key;
+
+    /// 模拟键盘, 记录按住的按键
+    struct Keyboard {
+        matcher: Matcher,
+        held: HashSet<KeyOrButton>,
+        now: Instant,
+    }
+
+    impl Keyboard {
+        fn new(steps: Vec<Vec<KeyOrButton>>, long_press: Option<u64>) -> Self {
+            let trigger = Trigger {
+                steps,
+                long_press: long_press.map(Duration::from_millis),
+                timeout: Duration::from_millis(500),
+            };
+            Self {
+                matcher: Matcher::new(trigger),
+                held: HashSet::new(),
+                now: Instant::now(),
+            }
+        }
+
+        fn wait(&mut self, ms: u64) -> Option<Action> {
+            self.now += Duration::from_millis(ms);
+            self.matcher.tick(self.now)
+        }
+
+        fn down(&mut self, key: Key) -> Option<Action> {
+            self.held.insert(K(key));
+            self.matcher.down(&K(key), &self.held, self.now)
+        }
+
+        fn up(&mut self, key: Key) -> Option<Action> {
+            self.held.remove(&K(key));
+            self.matcher.up(&K(key))
+        }
+
+        /// 按下并松开, 返回按下时的结果
+        fn tap(&mut self, key: Key) -> Option<Action> {
+            let action = self.down(key);
+            self.up(key);
+            action
+        }
+    }
+
+    #[test]
+    fn chord_needs_all_keys_held() {
+        let mut kb = Keyboard::new(vec![vec![K(Key::ControlLeft), K(Key::KeyK)]], None);
+        assert_eq!(kb.tap(Key::KeyK), None);
+        assert_eq!(kb.down(Key::ControlLeft), None);
+        assert_eq!(kb.down(Key::KeyK), Some(Action::Pressed));
+        // 已触发时再按不会重复触发, 松开最后一步的任一按键结束
+        assert_eq!(kb.down(Key::KeyK), None);
+        assert_eq!(kb.up(Key::ControlLeft), Some(Action::Released));
+        assert_eq!(kb.up(Key::KeyK), None);
+    }
+
+    #[test]
+    fn sequence_is_interrupted_by_other_keys() {
+        let mut kb = Keyboard::new(vec![vec![K(Key::KeyG)], vec![K(Key::KeyG)]], None);
+        assert_eq!(kb.tap(Key::KeyG), None);
+        assert_eq!(kb.tap(Key::KeyA), None);
+        assert_eq!(kb.tap(Key::KeyG), None);
+        assert_eq!(kb.down(Key::KeyG), Some(Action::Pressed));
+        assert_eq!(kb.up(Key::KeyG), Some(Action::Released));
+    }
+
+    #[test]
+    fn wrong_key_restarts_from_first_step() {
+        let mut kb = Keyboard::new(vec![vec![K(Key::KeyA)], vec![K(Key::KeyB)]], None);
+        assert_eq!(kb.tap(Key::KeyA), None);
+        // 按错的键正好是第一步时作为新的开始
+        assert_eq!(kb.tap(Key::KeyA), None);
+        assert_eq!(kb.tap(Key::KeyB), Some(Action::Pressed));
+    }
+
+    #[test]
+    fn sequence_times_out() {
+        let mut kb = Keyboard::new(vec![vec![K(Key::KeyG)], vec![K(Key::KeyG)]], None);
+        assert_eq!(kb.tap(Key::KeyG), None);
+        kb.wait(600);
+        assert_eq!(kb.tap(Key::KeyG), None);
+        kb.wait(400);
+        assert_eq!(kb.tap(Key::KeyG), Some(Action::Pressed));
+    }
+
+    #[test]
+    fn long_press() {
+        let mut kb = Keyboard::new(vec![vec![K(Key::F1)]], Some(800));
+        assert_eq!(kb.down(Key::F1), None);
+        assert_eq!(kb.matcher.deadline(), Some(kb.now + Duration::from_millis(800)));
+        assert_eq!(kb.wait(500), None);
+        // 时间不够时松开不会触发
+        assert_eq!(kb.up(Key::F1), None);
+        assert_eq!(kb.wait(500), None);
+
+        assert_eq!(kb.down(Key::F1), None);
+        assert_eq!(kb.wait(800), Some(Action::Pressed));
+        assert_eq!(kb.wait(800), None);
+        assert_eq!(kb.up(Key::F1), Some(Action::Released));
+    }
+
+    #[test]
+    fn display() {
+        let trigger = Trigger {
+            steps: vec![
+                vec![K(Key::ControlLeft), K(Key::KeyA)],
+                vec![KeyOrButton::Mouse(rdev::Button::Left)],
+            ],
+            long_press: Some(Duration::from_millis(500)),
+            timeout: Duration::from_millis(500),
+        };
+        assert_eq!(trigger.to_string(), "ControlLeft+KeyA, MouseLeft (长按 500ms)");
+    }
+
+    #[test]
+    fn swallow_final_key_of_last_step() {
+        let swallow = |steps| Trigger { steps, long_press: None, timeout: Duration::ZERO }.swallow();
+        let chord = vec![K(Key::KeyK), K(Key::ControlLeft), K(Key::ShiftLeft)];
+        let expected = Swallow {
+            key: K(Key::KeyK),
+            modifiers: vec![K(Key::ControlLeft), K(Key::ShiftLeft)],
+        };
+        assert_eq!(swallow(vec![vec![K(Key::KeyA)], chord]), Some(expected.clone()));
+        assert_eq!(expected.to_string(), "ControlLeft+ShiftLeft+KeyK");
+
+        // 都是修饰键时取最后一个
+        let chord = vec![K(Key::ControlLeft), K(Key::Alt)];
+        let expected = Swallow { key: K(Key::Alt), modifiers: vec![K(Key::ControlLeft)] };
+        assert_eq!(swallow(vec![chord]), Some(expected));
+        assert_eq!(swallow(vec![]), None);
+    }
+}
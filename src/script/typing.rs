@@ -0,0 +1,129 @@
+use rdev::Key;
+
+/// 字符在美式键盘上对应的按键及是否需要按住 Shift, 不在键盘上的字符为 None
+pub fn key(ch: char) -> Option<(Key, bool)> {
+    use Key::*;
+
+    const LETTERS: [Key; 26] = [
+        KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM, KeyN, KeyO, KeyP, KeyQ, KeyR,
+        KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    ];
+    const DIGITS: [Key; 10] = [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9];
+
+    Some(match ch {
+        'a'..='z' => (LETTERS[ch as usize - 'a' as usize], false),
+        'A'..='Z' => (LETTERS[ch as usize - 'A' as usize], true),
+        '0'..='9' => (DIGITS[ch as usize - '0' as usize], false),
+        ')' => (Num0, true),
+        '!' => (Num1, true),
+        '@' => (Num2, true),
+        '#' => (Num3, true),
+        '$' => (Num4, true),
+        '%' => (Num5, true),
+        '^' => (Num6, true),
+        '&' => (Num7, true),
+        '*' => (Num8, true),
+        '(' => (Num9, true),
+        ' ' => (Space, false),
+        '\n' => (Return, false),
+        '\t' => (Tab, false),
+        '-' => (Minus, false),
+        '_' => (Minus, true),
+        '=' => (Equal, false),
+        '+' => (Equal, true),
+        '[' => (LeftBracket, false),
+        '{' => (LeftBracket, true),
+        ']' => (RightBracket, false),
+        '}' => (RightBracket, true),
+        '\\' => (BackSlash, false),
+        '|' => (BackSlash, true),
+        ';' => (SemiColon, false),
+        ':' => (SemiColon, true),
+        '\'' => (Quote, false),
+        '"' => (Quote, true),
+        '`' => (BackQuote, false),
+        '~' => (BackQuote, true),
+        ',' => (Comma, false),
+        '<' => (Comma, true),
+        '.' => (Dot, false),
+        '>' => (Dot, true),
+        '/' => (Slash, false),
+        '?' => (Slash, true),
+        _ => return None,
+    })
+}
+
+/// 把字符的 keysym 临时映射到空闲的 keycode 上并模拟按下, 可以输入任意 Unicode 字符
+///
+/// 同一次调用中的字符共用连接, 空闲的 keycode 足够时只在最后等待并还原一次映射
+///
+/// # Safety
+/// display 必须是通过 xconn 打开的有效连接, 出错时才能记录而不是退出进程; 且调用期间没有其他线程使用
+#[cfg(target_os = "linux")]
+pub unsafe fn unicode(display: *mut x11::xlib::Display, text: &str) -> Result<(), String> {
+    use std::{os::raw::c_int, slice, thread, time::Duration};
+
+    use x11::{xlib, xtest};
+
+    use crate::script::xconn;
+
+    // Latin-1 的 keysym 即码位, 其余为 0x01000000 加码位
+    let keysym = |ch: char| {
+        (match ch as u32 {
+            code @ (0x20..=0x7e | 0xa0..=0xff) => code,
+            code => 0x0100_0000 + code,
+        }) as xlib::KeySym
+    };
+
+    unsafe {
+        // 从后往前找没有映射任何 keysym 的 keycode
+        let (mut min, mut max) = (0, 0);
+        xlib::XDisplayKeycodes(display, &mut min, &mut max);
+        let mut per = 0;
+        let syms = xlib::XGetKeyboardMapping(display, min as u8, max - min + 1, &mut per);
+        if syms.is_null() {
+            return Err("无法读取键盘映射".into());
+        }
+        let all = slice::from_raw_parts(syms, ((max - min + 1) * per) as usize);
+        let codes: Vec<c_int> = all
+            .chunks(per as usize)
+            .enumerate()
+            .rev()
+            .filter(|(_, syms)| syms.iter().all(|s| *s == 0))
+            .map(|(i, _)| min + i as c_int)
+            .collect();
+        xlib::XFree(syms.cast());
+        if codes.is_empty() {
+            return Err("没有空闲的 keycode 可以映射".into());
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        let ((), failed) = xconn::checked(display, || {
+            for chunk in chars.chunks(codes.len()) {
+                for (ch, code) in chunk.iter().zip(&codes) {
+                    let mut syms = [keysym(*ch), keysym(*ch)];
+                    xlib::XChangeKeyboardMapping(display, *code, 2, syms.as_mut_ptr(), 1);
+                }
+                xlib::XSync(display, xlib::False);
+                for code in &codes[..chunk.len()] {
+                    xtest::XTestFakeKeyEvent(display, *code as u32, xlib::True, 0);
+                    xtest::XTestFakeKeyEvent(display, *code as u32, xlib::False, 0);
+                }
+                xlib::XSync(display, xlib::False);
+
+                // 等窗口按新的映射处理完按键再还原, 否则可能读到还原后的映射
+                thread::sleep(Duration::from_millis(20));
+                for code in &codes[..chunk.len()] {
+                    let mut syms = [0, 0];
+                    xlib::XChangeKeyboardMapping(display, *code, 2, syms.as_mut_ptr(), 1);
+                }
+                xlib::XSync(display, xlib::False);
+            }
+        });
+
+        match failed {
+            true => Err(format!("输入 {text:?} 时发生 X11 错误")),
+            false => Ok(()),
+        }
+    }
+}
@@ -1,80 +1,351 @@
-use std::{collections::HashMap, fmt::Write, sync::Arc};
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::{Arc, Mutex},
+};
 
-use druid::{
-    theme::TEXT_COLOR,
-    widget::{CrossAxisAlignment, Flex, Label},
-    *,
+use tokio::{
+    runtime::Handle,
+    sync::{
+        broadcast,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+    },
 };
-use tokio::sync::mpsc::{self, UnboundedSender};
 
-use crate::script::Title;
+use crate::script::Status;
 
-pub const MY_FONT: Key<FontDescriptor> = Key::new("my_font");
+#[cfg(feature = "gui")]
+pub use gui::{AppData, MY_FONT};
 
 /// 显示运行中脚本的窗口
 pub struct WindowList {
-    pub app: AppLauncher<AppData>,
-    pub app_data: AppData,
-    pub updater: UnboundedSender<Title>,
+    pub updater: UnboundedSender<Status>,
+    pub notifier: UnboundedSender<Notice>,
+    /// 转发脚本运行状态的变化, 供窗口以外的界面订阅
+    pub status: broadcast::Sender<Status>,
+    /// 窗口位置
+    pub point: (f64, f64),
+    /// 字体大小
+    pub font_size: f64,
+    /// 字体颜色
+    pub font_color: (u8, u8, u8),
+    /// 是否显示边框
+    pub border: bool,
+    pub(crate) titles: UnboundedReceiver<Status>,
+    pub(crate) notices: UnboundedReceiver<Notice>,
+}
+
+/// 发送给窗口的通知
+#[derive(Debug, Clone)]
+pub enum Notice {
+    /// 提示信息(如配置重载结果), 显示几秒后消失
+    Message(String),
+    /// 当前生效的方案
+    Profile(Option<Arc<String>>),
+    /// 当前的层, 最后一个位于最上方
+    Layers(Vec<String>),
+    /// 加载或重载后的所有脚本的共享状态, 按配置中的顺序; 当前步骤只写入共享状态, 需要定时读取
+    Scripts(Vec<Arc<Mutex<Status>>>),
 }
 
 impl WindowList {
-    pub fn run(self) -> Result<(), PlatformError> {
-        self.app.launch(self.app_data)
+    pub fn init(point: (f64, f64), font_size: f64, font_color: (u8, u8, u8), border: bool) -> Self {
+        let (updater, titles) = mpsc::unbounded_channel();
+        let (notifier, notices) = mpsc::unbounded_channel();
+        let (status, _) = broadcast::channel(64);
+        Self {
+            updater,
+            notifier,
+            status,
+            point,
+            font_size,
+            font_color,
+            border,
+            titles,
+            notices,
+        }
     }
 
-    pub fn init(point: impl Into<Point>, font_size: f64, font_color: (u8, u8, u8), border: bool) -> Self {
-        let (updater, mut rx) = mpsc::unbounded_channel::<Title>();
-        let window_handle = WindowHandle::default();
-        let window = WindowDesc::new(ui_builder())
-            .title("脚本列表")
-            .transparent(true)
-            .set_position(point)
-            .show_titlebar(border)
-            .set_always_on_top(true)
-            .window_size_policy(WindowSizePolicy::Content)
-            .set_level(WindowLevel::Tooltip(window_handle));
-        let app = AppLauncher::with_window(window).configure_env(move |env: &mut Env, _data: &AppData| {
-            let new_font = FontDescriptor::new(FontFamily::SYSTEM_UI)
-                .with_size(font_size)
-                .with_weight(FontWeight::BLACK);
-            env.set(MY_FONT, new_font);
-            env.set(TEXT_COLOR, Color::rgb8(font_color.0, font_color.1, font_color.2));
-        });
-
-        let ext = app.get_external_handle();
-        tokio::spawn(async move {
-            while let Some((title, state)) = rx.recv().await {
-                ext.add_idle_callback(move |data: &mut AppData| {
-                    data.titles.insert(title, state);
-                });
-            }
-        });
+    /// 不显示窗口, 把运行状态输出到终端, 阻塞当前线程
+    ///
+    /// 终端中在最后一行显示方案, 层和运行中的脚本; 输出被重定向时每次变化输出一行日志
+    pub fn run_headless(self) {
+        tokio::task::block_in_place(|| Handle::current().block_on(self.log()))
+    }
+
+    async fn log(mut self) {
+        let terminal = io::stdout().is_terminal();
+        let mut titles: Vec<(Arc<String>, bool)> = vec![];
+        let mut profile = None;
+        let mut layers = vec![];
+        loop {
+            // 日志行, 以及是否是状态行无法体现的提示信息
+            let (line, message) = tokio::select! {
+                Some(status) = self.titles.recv() => {
+                    let _ = self.status.send(status.clone());
+                    let (state, title) = (status.running(), status.title);
+                    match titles.iter_mut().find(|(t, _)| *t == title) {
+                        Some((_, s)) if *s == state => continue,
+                        Some((_, s)) => *s = state,
+                        None => titles.push((title.clone(), state)),
+                    }
+                    (format!("{} {title}", if state { "启动" } else { "终止" }), false)
+                }
+                Some(notice) = self.notices.recv() => match notice {
+                    Notice::Message(message) => (message, true),
+                    Notice::Profile(p) => {
+                        profile = p;
+                        (format!("方案: {}", profile.as_deref().map_or("无", |p| p.as_str())), false)
+                    }
+                    Notice::Layers(l) => {
+                        layers = l;
+                        (format!("层: {}", layers.join(" > ")), false)
+                    }
+                    Notice::Scripts(scripts) => {
+                        titles = scripts
+                            .iter()
+                            .map(|s| {
+                                let s = s.lock().unwrap();
+                                (s.title.clone(), s.running())
+                            })
+                            .collect();
+                        continue;
+                    }
+                },
+                else => return,
+            };
 
-        Self { app, app_data: AppData::default(), updater }
+            let mut stdout = io::stdout().lock();
+            if !terminal {
+                let _ = writeln!(stdout, "{line}");
+                continue;
+            }
+            if message {
+                let _ = writeln!(stdout, "\r\x1b[K{line}");
+            }
+            let mut status = String::new();
+            if let Some(profile) = &profile {
+                status.push_str(&format!("[{profile}] "));
+            }
+            if !layers.is_empty() {
+                status.push_str(&format!("层: {} ", layers.join(" > ")));
+            }
+            let running: Vec<&str> = titles.iter().filter(|(_, s)| *s).map(|(t, _)| t.as_str()).collect();
+            status.push_str(&format!("运行中: {}", running.join(", ")));
+            let _ = write!(stdout, "\r\x1b[K{status}");
+            let _ = stdout.flush();
+        }
     }
 }
 
-#[derive(Debug, Clone, Default, Data)]
-pub struct AppData {
-    #[data(eq)]
-    pub titles: HashMap<Arc<String>, bool>,
-}
+#[cfg(feature = "gui")]
+mod gui {
+    use std::{
+        fmt::Write,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use druid::{
+        theme::TEXT_COLOR,
+        widget::{CrossAxisAlignment, Flex, Label},
+        *,
+    };
+    use tokio::{
+        sync::Notify,
+        time::{interval, sleep},
+    };
+
+    use crate::script::{
+        window::{Notice, WindowList},
+        Status,
+    };
+
+    pub const MY_FONT: Key<FontDescriptor> = Key::new("my_font");
+
+    impl WindowList {
+        /// 显示窗口, 阻塞直到窗口关闭
+        pub fn run(self) -> Result<(), PlatformError> {
+            let Self {
+                status,
+                point,
+                font_size,
+                font_color,
+                border,
+                mut titles,
+                mut notices,
+                ..
+            } = self;
+            let window_handle = WindowHandle::default();
+            let window = WindowDesc::new(ui_builder())
+                .title("脚本列表")
+                .transparent(true)
+                .set_position(point)
+                .show_titlebar(border)
+                .set_always_on_top(true)
+                .window_size_policy(WindowSizePolicy::Content)
+                .set_level(WindowLevel::Tooltip(window_handle));
+            let app = AppLauncher::with_window(window).configure_env(move |env: &mut Env, _data: &AppData| {
+                let new_font = FontDescriptor::new(FontFamily::SYSTEM_UI)
+                    .with_size(font_size)
+                    .with_weight(FontWeight::BLACK);
+                env.set(MY_FONT, new_font);
+                env.set(TEXT_COLOR, Color::rgb8(font_color.0, font_color.1, font_color.2));
+            });
 
-fn ui_builder() -> impl Widget<AppData> {
-    Flex::column()
-        .cross_axis_alignment(CrossAxisAlignment::Start)
-        .with_child(
-            Label::new(|data: &AppData, _: &_| {
-                let mut s = String::new();
-                for (title, state) in &data.titles {
-                    if *state {
-                        writeln!(&mut s, "{title}").unwrap();
+            // 状态变化时唤醒刷新的任务
+            let wake = Arc::new(Notify::new());
+            let ext = app.get_external_handle();
+            let woken = wake.clone();
+            tokio::spawn(async move {
+                while let Some(update) = titles.recv().await {
+                    woken.notify_one();
+                    let _ = status.send(update.clone());
+                    ext.add_idle_callback(move |data: &mut AppData| {
+                        match data.scripts.iter_mut().find(|s| s.title == update.title) {
+                            Some(s) => *s = update,
+                            None => data.scripts.push(update),
+                        }
+                    });
+                }
+            });
+
+            // 运行中时定时读取共享的状态, 刷新当前步骤, 用时和转圈; 都停止后等下次状态变化
+            let shared: Arc<Mutex<Vec<Arc<Mutex<Status>>>>> = Default::default();
+            let ext = app.get_external_handle();
+            let scripts = shared.clone();
+            let woken = wake.clone();
+            tokio::spawn(async move {
+                loop {
+                    woken.notified().await;
+                    let mut interval = interval(Duration::from_millis(100));
+                    loop {
+                        interval.tick().await;
+                        let scripts: Vec<Status> = scripts
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .map(|s| s.lock().unwrap().clone())
+                            .collect();
+                        let running = scripts.iter().any(Status::running);
+                        ext.add_idle_callback(move |data: &mut AppData| {
+                            data.scripts = scripts;
+                            data.tick += 1;
+                        });
+                        if !running {
+                            break;
+                        }
                     }
                 }
-                s
-            })
-            .with_font(MY_FONT),
+            });
+
+            let ext = app.get_external_handle();
+            tokio::spawn(async move {
+                while let Some(notice) = notices.recv().await {
+                    let message = match notice {
+                        Notice::Message(message) => {
+                            // 窗口中的提示几秒后消失, 同时输出到终端
+                            println!("{message}");
+                            Arc::new(message)
+                        }
+                        Notice::Profile(profile) => {
+                            ext.add_idle_callback(move |data: &mut AppData| data.profile = profile);
+                            continue;
+                        }
+                        Notice::Layers(layers) => {
+                            ext.add_idle_callback(move |data: &mut AppData| data.layers = layers);
+                            continue;
+                        }
+                        Notice::Scripts(scripts) => {
+                            let current = scripts.iter().map(|s| s.lock().unwrap().clone()).collect();
+                            *shared.lock().unwrap() = scripts;
+                            ext.add_idle_callback(move |data: &mut AppData| data.scripts = current);
+                            // 重载后保留的脚本可能仍在运行
+                            wake.notify_one();
+                            continue;
+                        }
+                    };
+                    let current = message.clone();
+                    ext.add_idle_callback(move |data: &mut AppData| data.message = current);
+
+                    let ext = ext.clone();
+                    tokio::spawn(async move {
+                        sleep(Duration::from_secs(5)).await;
+                        ext.add_idle_callback(move |data: &mut AppData| {
+                            if Arc::ptr_eq(&data.message, &message) {
+                                data.message = Default::default();
+                            }
+                        });
+                    });
+                }
+            });
+
+            app.launch(AppData::default())
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Data)]
+    pub struct AppData {
+        /// 所有脚本的状态, 按配置中的顺序
+        #[data(eq)]
+        pub scripts: Vec<Status>,
+        pub message: Arc<String>,
+        pub profile: Option<Arc<String>>,
+        #[data(eq)]
+        pub layers: Vec<String>,
+        /// 运行中时定时增加, 用于刷新用时和转圈
+        pub tick: u64,
+    }
+
+    /// 运行中脚本的一行: 有限循环显示进度条和次数, 无限循环显示转圈和轮数, 以及当前方法和用时
+    fn progress(status: &Status) -> String {
+        const WIDTH: usize = 10;
+        const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+        let elapsed = status.elapsed();
+        let round = match status.progress() {
+            Some(progress) => {
+                let done = (progress * WIDTH as f64).round() as usize;
+                let bar = "█".repeat(done) + &"░".repeat(WIDTH - done);
+                format!("{bar} {}/{}", status.iteration + 1, status.repeat)
+            }
+            None => {
+                let spinner = SPINNER[(elapsed.as_millis() / 100) as usize % SPINNER.len()];
+                format!("{spinner} 第 {} 轮", status.iteration + 1)
+            }
+        };
+        let secs = elapsed.as_secs();
+        format!(
+            "{} {round} 步骤 {}/{} {:02}:{:02}",
+            status.title,
+            status.method + 1,
+            status.methods,
+            secs / 60,
+            secs % 60
         )
-        .background(Color::TRANSPARENT)
+    }
+
+    fn ui_builder() -> impl Widget<AppData> {
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(
+                Label::new(|data: &AppData, _: &_| {
+                    let mut s = String::new();
+                    if let Some(profile) = &data.profile {
+                        writeln!(&mut s, "[{profile}]").unwrap();
+                    }
+                    if !data.layers.is_empty() {
+                        writeln!(&mut s, "层: {}", data.layers.join(" > ")).unwrap();
+                    }
+                    for status in data.scripts.iter().filter(|s| s.running()) {
+                        writeln!(&mut s, "{}", progress(status)).unwrap();
+                    }
+                    if !data.message.is_empty() {
+                        writeln!(&mut s, "{}", data.message).unwrap();
+                    }
+                    s
+                })
+                .with_font(MY_FONT),
+            )
+            .background(Color::TRANSPARENT)
+    }
 }
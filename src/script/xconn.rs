@@ -0,0 +1,100 @@
+use std::{
+    mem,
+    os::raw::c_int,
+    ptr,
+    sync::{Mutex, OnceLock},
+};
+
+use x11::xlib;
+
+/// 复用的 X11 连接, 第一次使用时打开, 之后不再重复连接
+#[derive(Debug, Default)]
+pub struct XConnection(Mutex<Option<Display>>);
+
+#[derive(Debug)]
+struct Display(*mut xlib::Display);
+
+// 只在持有锁时使用连接
+unsafe impl Send for Display {}
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        unsafe { close(self.0) };
+    }
+}
+
+impl XConnection {
+    /// 持有连接运行 f, 同一时间只有一个线程使用连接
+    pub fn with<T>(&self, f: impl FnOnce(*mut xlib::Display) -> Result<T, String>) -> Result<T, String> {
+        let mut display = self.0.lock().unwrap();
+        if display.is_none() {
+            *display = Some(Display(open()?));
+        }
+        f(display.as_ref().unwrap().0)
+    }
+}
+
+/// 通过 open 打开的连接及其是否发生了错误
+static ERRORS: Mutex<Vec<(usize, bool)>> = Mutex::new(Vec::new());
+
+type ErrorHandler = Option<unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int>;
+
+/// 安装错误处理之前的处理, 用于其他连接的错误
+static PREVIOUS: OnceLock<ErrorHandler> = OnceLock::new();
+
+/// 打开 X11 连接, 连接上的错误只做记录, 由 [`checked`] 取出
+///
+/// 默认的错误处理会直接退出进程; 错误处理是全局的, 只在第一次打开时安装一次, 其他连接的错误仍交给之前的处理
+pub fn open() -> Result<*mut xlib::Display, String> {
+    PREVIOUS.get_or_init(|| unsafe { xlib::XSetErrorHandler(Some(on_error)) });
+
+    let display = unsafe { xlib::XOpenDisplay(ptr::null()) };
+    if display.is_null() {
+        return Err("无法连接 X11 显示".into());
+    }
+    ERRORS.lock().unwrap().push((display as usize, false));
+    Ok(display)
+}
+
+/// 关闭通过 [`open`] 打开的连接
+///
+/// # Safety
+/// display 必须是 open 返回且未关闭的连接
+pub unsafe fn close(display: *mut xlib::Display) {
+    ERRORS.lock().unwrap().retain(|(d, _)| *d != display as usize);
+    xlib::XCloseDisplay(display);
+}
+
+/// 运行 f 并等待请求都处理完, 返回 f 的结果和期间连接上是否发生了错误
+///
+/// # Safety
+/// display 必须是 open 返回且未关闭的连接, 且调用期间没有其他线程使用
+pub unsafe fn checked<T>(display: *mut xlib::Display, f: impl FnOnce() -> T) -> (T, bool) {
+    xlib::XSync(display, xlib::False);
+    take_error(display);
+    let value = f();
+    xlib::XSync(display, xlib::False);
+    (value, take_error(display))
+}
+
+/// 取出并清除连接上记录的错误
+fn take_error(display: *mut xlib::Display) -> bool {
+    let mut errors = ERRORS.lock().unwrap();
+    errors
+        .iter_mut()
+        .find(|(d, _)| *d == display as usize)
+        .is_some_and(|(_, failed)| mem::take(failed))
+}
+
+unsafe extern "C" fn on_error(display: *mut xlib::Display, event: *mut xlib::XErrorEvent) -> c_int {
+    let mut errors = ERRORS.lock().unwrap();
+    if let Some((_, failed)) = errors.iter_mut().find(|(d, _)| *d == display as usize) {
+        *failed = true;
+        return 0;
+    }
+    drop(errors);
+    match PREVIOUS.get().copied().flatten() {
+        Some(previous) => previous(display, event),
+        None => 0,
+    }
+}
@@ -1,12 +1,15 @@
+#[cfg(target_os = "windows")]
+use std::os::windows::fs::OpenOptionsExt;
 use std::{
     env,
     fs::File,
     io::{self, Write},
-    os::windows::fs::OpenOptionsExt,
     process,
     thread::sleep,
     time::Duration,
 };
+#[cfg(unix)]
+use std::{fs, os::unix::io::AsRawFd, path::PathBuf};
 
 pub struct SingApp {
     #[allow(unused)]
@@ -64,3 +67,84 @@ impl SingApp {
         }
     }
 }
+
+#[cfg(unix)]
+impl SingApp {
+    fn path(extension: &str) -> PathBuf {
+        env::current_exe().unwrap().with_extension(extension)
+    }
+
+    /// 给锁文件加 flock 排它锁; block 为 false 时锁被占用直接返回错误
+    fn lock(block: bool) -> io::Result<File> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(Self::path("lock"))?;
+        let operation = if block {
+            libc::LOCK_EX
+        } else {
+            libc::LOCK_EX | libc::LOCK_NB
+        };
+        match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+            0 => Ok(file),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    fn write_pid() -> io::Result<()> {
+        let mut file = File::create(Self::path("pid"))?;
+        write!(&mut file, "{}", process::id())
+    }
+
+    pub fn run() -> Self {
+        match Self::lock(false) {
+            Ok(lock) => {
+                if let Err(err) = Self::write_pid() {
+                    eprintln!("{err}");
+                }
+                Self { lock }
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(0)
+            }
+        }
+    }
+
+    pub fn run_current() -> io::Result<Self> {
+        if let Ok(lock) = Self::lock(false) {
+            Self::write_pid()?;
+            return Ok(Self { lock });
+        }
+
+        let pid = fs::read_to_string(Self::path("pid"))?;
+        let pid: libc::pid_t = pid
+            .trim()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // 0 和负数会发给整个进程组或所有进程
+        if pid <= 0 || pid as u32 == process::id() {
+            let err = format!("pid 文件中的 {pid} 无效");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+
+        // 先 SIGTERM 等待退出, 超时再 SIGKILL
+        if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+            process::exit(0)
+        }
+        for _ in 0..30 {
+            sleep(Duration::from_millis(100));
+            if let Ok(lock) = Self::lock(false) {
+                Self::write_pid()?;
+                return Ok(Self { lock });
+            }
+        }
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+
+        let lock = Self::lock(true)?;
+        Self::write_pid()?;
+        Ok(Self { lock })
+    }
+}
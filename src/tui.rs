@@ -0,0 +1,400 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    mem,
+    sync::Arc,
+};
+
+use tokio::{
+    runtime::Handle,
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{self, UnboundedSender},
+};
+
+use crate::{
+    ctl::{Body, Handler, Request},
+    script::{
+        window::{Notice, WindowList},
+        Control, State, Status,
+    },
+};
+
+/// 终端界面的按键
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    Up,
+    Down,
+    /// 启动或终止选中的脚本
+    Toggle,
+    StopAll,
+    Reload,
+    Quit,
+}
+
+/// 终端中的脚本列表, 显示所有脚本的触发按键, 状态, 循环次数和错误, 可以启动或终止选中的脚本
+pub struct Tui {
+    pub handler: Handler,
+    /// 所有脚本的状态及触发按键, 按配置中的顺序
+    scripts: Vec<(Status, String)>,
+    selected: usize,
+    profile: Option<Arc<String>>,
+    layers: Vec<String>,
+    message: String,
+}
+
+impl Tui {
+    pub fn new(handler: Handler) -> Self {
+        Self {
+            handler,
+            scripts: vec![],
+            selected: 0,
+            profile: None,
+            layers: vec![],
+            message: String::new(),
+        }
+    }
+
+    /// 显示界面, 阻塞当前线程直到按下 q
+    pub fn run(self, window: WindowList) -> io::Result<()> {
+        let _raw = RawMode::enable()?;
+        tokio::task::block_in_place(|| Handle::current().block_on(self.event_loop(window)))
+    }
+
+    async fn event_loop(mut self, window: WindowList) -> io::Result<()> {
+        let WindowList { status, mut titles, mut notices, .. } = window;
+        let (tx, mut inputs) = mpsc::unbounded_channel();
+        std::thread::spawn(move || read_inputs(tx));
+        let mut resize = signal(SignalKind::window_change())?;
+
+        self.refresh().await;
+        loop {
+            self.draw()?;
+            tokio::select! {
+                Some(update) = titles.recv() => {
+                    let _ = status.send(update.clone());
+                    match self.scripts.iter_mut().find(|(s, _)| s.title == update.title) {
+                        Some((s, _)) => *s = update,
+                        None => self.refresh().await,
+                    }
+                }
+                Some(notice) = notices.recv() => {
+                    match notice {
+                        Notice::Message(message) => self.message = message,
+                        Notice::Profile(profile) => self.profile = profile,
+                        Notice::Layers(layers) => self.layers = layers,
+                        Notice::Scripts(_) => {}
+                    }
+                    // 重载后脚本可能变化
+                    self.refresh().await;
+                }
+                Some(input) = inputs.recv() => match input {
+                    Input::Up => self.selected = self.selected.saturating_sub(1),
+                    Input::Down => self.selected = (self.selected + 1).min(self.scripts.len().saturating_sub(1)),
+                    Input::Toggle => {
+                        let Some((status, _)) = self.scripts.get(self.selected) else { continue };
+                        let title = status.title.to_string();
+                        let request = match status.running() {
+                            true => Request::Stop { title },
+                            false => Request::Start { title },
+                        };
+                        self.request(request).await;
+                    }
+                    Input::StopAll => self.request(Request::StopAll).await,
+                    Input::Reload => self.request(Request::Reload).await,
+                    Input::Quit => return Ok(()),
+                },
+                _ = resize.recv() => {}
+                else => return Ok(()),
+            }
+        }
+    }
+
+    /// 重新获取所有脚本的状态
+    async fn refresh(&mut self) {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        if self.handler.control.send(Control::Status(tx)).is_err() {
+            return;
+        }
+        if let Ok(snapshot) = rx.await {
+            self.scripts = snapshot.scripts;
+            self.profile = snapshot.profile;
+            self.layers = snapshot.layers;
+            self.selected = self.selected.min(self.scripts.len().saturating_sub(1));
+        }
+    }
+
+    /// 发送控制指令, 失败时显示原因
+    async fn request(&mut self, request: Request) {
+        let res = self.handler.handle(request).await;
+        if let Body::Error { error } = res.body {
+            self.message = error;
+        }
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let (rows, cols) = size();
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.render(rows, cols).as_bytes())?;
+        stdout.flush()
+    }
+
+    /// 按终端的行数和列数生成整个界面
+    fn render(&self, rows: usize, cols: usize) -> String {
+        let trigger_width = self
+            .scripts
+            .iter()
+            .map(|(_, t)| width(t))
+            .max()
+            .unwrap_or(0)
+            .clamp(4, 30);
+        let title_width = self
+            .scripts
+            .iter()
+            .map(|(s, _)| width(&s.title))
+            .max()
+            .unwrap_or(0)
+            .clamp(4, 30);
+
+        // 回到左上角逐行覆盖, 每行清除行尾的旧内容, 不清屏以免闪烁
+        let mut s = String::from("\x1b[H");
+        let line = |s: &mut String, text: &str| {
+            let _ = write!(s, "{}\x1b[K\r\n", truncate(text, cols));
+        };
+        let mut header = String::from("kmm");
+        if let Some(profile) = &self.profile {
+            let _ = write!(header, "  方案: {profile}");
+        }
+        if !self.layers.is_empty() {
+            let _ = write!(header, "  层: {}", self.layers.join(" > "));
+        }
+        line(&mut s, &header);
+        line(&mut s, "");
+        let head = format!(
+            "  {} {} {} {:>6}  错误",
+            pad("标题", title_width),
+            pad("触发", trigger_width),
+            pad("状态", 6),
+            "次数"
+        );
+        line(&mut s, &head);
+
+        // 标题, 表头和底部各占两行
+        let visible = rows.saturating_sub(6).max(1);
+        let skip = (self.selected + 1).saturating_sub(visible);
+        let mut lines = 3;
+        for (i, (status, trigger)) in self.scripts.iter().enumerate().skip(skip).take(visible) {
+            lines += 1;
+            let text = row(i == self.selected, status, trigger, title_width, trigger_width);
+            match (i == self.selected, status.running()) {
+                (true, _) => s.push_str("\x1b[7m"),
+                (false, true) => s.push_str("\x1b[32m"),
+                _ => {}
+            }
+            s.push_str(&truncate(&text, cols));
+            s.push_str("\x1b[0m\x1b[K\r\n");
+        }
+
+        // 空行补到底部两行之前
+        for _ in lines..rows.saturating_sub(2) {
+            line(&mut s, "");
+        }
+        line(&mut s, &self.message);
+        s.push_str(&truncate(
+            "↑/↓ 选择  Enter/空格 启动或终止  s 全部终止  r 重载配置  q 退出",
+            cols,
+        ));
+        s.push_str("\x1b[K\x1b[J");
+        s
+    }
+}
+
+/// 一个脚本的一行: 选中标记, 标题, 触发按键, 状态, 循环次数和错误
+fn row(selected: bool, status: &Status, trigger: &str, title_width: usize, trigger_width: usize) -> String {
+    let state = match status.state {
+        State::Idle => "空闲",
+        State::Running => "运行中",
+        State::Looping => "循环中",
+    };
+    format!(
+        "{} {} {} {} {:>6}  {}",
+        if selected { ">" } else { " " },
+        pad(&status.title, title_width),
+        pad(trigger, trigger_width),
+        pad(state, 6),
+        status.iteration,
+        status.error.as_deref().map_or("", |e| e.as_str()).replace('\n', " "),
+    )
+}
+
+/// 读取终端输入, 直到界面退出
+fn read_inputs(tx: UnboundedSender<Input>) {
+    let mut buf = [0; 32];
+    let mut stdin = io::stdin().lock();
+    while let Ok(n @ 1..) = stdin.read(&mut buf) {
+        let input = match &buf[..n] {
+            b"\x1b[A" | b"\x1bOA" | b"k" => Some(Input::Up),
+            b"\x1b[B" | b"\x1bOB" | b"j" => Some(Input::Down),
+            b"\r" | b"\n" | b" " => Some(Input::Toggle),
+            b"s" => Some(Input::StopAll),
+            b"r" => Some(Input::Reload),
+            // Ctrl+C
+            b"q" | b"\x03" => Some(Input::Quit),
+            _ => None,
+        };
+        if let Some(input) = input {
+            if tx.send(input).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// 终端的行数和列数
+fn size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_row > 0 && size.ws_col > 0 => (size.ws_row as usize, size.ws_col as usize),
+        _ => (24, 80),
+    }
+}
+
+/// 字符在终端中的显示宽度, 中日韩文字和全角符号占两列
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115f | 0x2e80..=0xa4cf | 0xac00..=0xd7a3 | 0xf900..=0xfaff | 0xfe30..=0xfe4f | 0xff00..=0xff60 => 2,
+        0xffe0..=0xffe6 | 0x1f300..=0x1faff | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+fn width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// 截断到指定宽度
+fn truncate(s: &str, max: usize) -> String {
+    let mut width = 0;
+    s.chars()
+        .take_while(|c| {
+            width += char_width(*c);
+            width <= max
+        })
+        .collect()
+}
+
+/// 截断或补齐空格到指定宽度
+fn pad(s: &str, w: usize) -> String {
+    let mut s = truncate(s, w);
+    s.push_str(&" ".repeat(w - width(&s)));
+    s
+}
+
+/// 原始模式: 关闭回显和行缓冲, 使用备用屏幕; 离开时恢复
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = termios;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(Self(termios))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_tui(scripts: Vec<(Status, String)>, selected: usize) -> Tui {
+        let handler = Handler {
+            config: "config.toml".into(),
+            control: mpsc::unbounded_channel().0,
+            updater: mpsc::unbounded_channel().0,
+            notifier: mpsc::unbounded_channel().0,
+        };
+        Tui { scripts, selected, ..Tui::new(handler) }
+    }
+
+    fn status(title: &str, state: State, iteration: usize, error: Option<&str>) -> Status {
+        Status {
+            state,
+            iteration,
+            error: error.map(|e| Arc::new(e.to_string())),
+            ..Status::new(Arc::new(title.to_string()))
+        }
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('世'), 2);
+        assert_eq!(char_width('！'), 2);
+        assert_eq!(char_width('ｱ'), 1);
+        assert_eq!(width("a世😀"), 5);
+
+        assert_eq!(truncate("ab世界", 3), "ab");
+        assert_eq!(truncate("ab世界", 4), "ab世");
+        assert_eq!(truncate("ab", 0), "");
+        assert_eq!(pad("a", 3), "a  ");
+        assert_eq!(pad("世界", 3), "世 ");
+        assert_eq!(pad("世界x", 4), "世界");
+    }
+
+    #[test]
+    fn rows() {
+        let looping = status("启动", State::Looping, 3, None);
+        assert_eq!(row(true, &looping, "F1", 4, 7), "> 启动 F1      循环中      3  ");
+        let failed = status("B", State::Idle, 0, Some("出错\n了"));
+        assert_eq!(
+            row(false, &failed, "Ctrl+F2", 4, 7),
+            "  B    Ctrl+F2 空闲        0  出错 了"
+        );
+    }
+
+    #[test]
+    fn render_fills_the_screen() {
+        let scripts = vec![
+            (status("启动", State::Looping, 3, None), "F1".to_string()),
+            (status("B", State::Idle, 0, Some("出错")), "Ctrl+F2".to_string()),
+        ];
+        let mut tui = new_tui(scripts.clone(), 0);
+        tui.profile = Some(Arc::new("游戏".into()));
+        tui.layers = vec!["a".into(), "b".into()];
+        tui.message = "已重载".into();
+
+        let screen = tui.render(10, 20);
+        let lines: Vec<&str> = screen.split("\r\n").collect();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], "\x1b[Hkmm  方案: 游戏  层:\x1b[K");
+        assert_eq!(lines[3], "\x1b[7m> 启动 F1      循环\x1b[0m\x1b[K");
+        assert_eq!(lines[4], "  B    Ctrl+F2 空闲 \x1b[0m\x1b[K");
+        assert!(lines[5..8].iter().all(|line| *line == "\x1b[K"));
+        assert_eq!(lines[8], "已重载\x1b[K");
+        assert_eq!(lines[9], "↑/↓ 选择  Enter/空格\x1b[K\x1b[J");
+
+        // 行数不够时滚动到选中的脚本
+        let screen = new_tui(scripts, 1).render(7, 80);
+        let lines: Vec<&str> = screen.split("\r\n").collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[3], "\x1b[7m> B    Ctrl+F2 空闲        0  出错\x1b[0m\x1b[K");
+        assert_eq!(lines[4], "\x1b[K");
+    }
+}
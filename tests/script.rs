@@ -0,0 +1,512 @@
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    sync::{mpsc::Sender, Arc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use kmm::script::{
+    backend::RecordBackend,
+    clipboard::{Clipboard, MemoryClipboard},
+    config::{Config, KeyOrButton},
+    focus::{MockFocus, WindowInfo},
+    screen::{Frame, ImageScreen, Region},
+    trigger::Swallow,
+    Control, Runtime,
+};
+use rdev::Button;
+use rdev::{Event, EventType, Key};
+use tokio::{
+    runtime::Handle,
+    sync::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
+    task::yield_now,
+    time::Instant,
+};
+
+/// 全局配置, 后面接 [[scripts]]
+const HEADER: &str = r#"
+delay = 0
+scaling = 1
+offset = [0, 0]
+point = [0, 0]
+font_size = 20
+font_color = [0, 0, 0]
+border = false
+[blocks]
+"#;
+
+/// 运行中的脚本列表
+///
+/// 测试都暂停了时钟, 运行时空闲时直接跳到下一个计时器, 脚本中的等待不占用实际时间, 事件的时间可以精确比较
+struct Listening {
+    backend: Arc<RecordBackend>,
+    input: Sender<Event>,
+    /// 已输入的事件数
+    sent: Cell<usize>,
+    control: UnboundedSender<Control>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Listening {
+    /// 输入按下和松开, 等监听的线程把事件转给脚本列表后返回
+    async fn tap(&self, key: Key) {
+        self.input.send(event(EventType::KeyPress(key))).unwrap();
+        self.input.send(event(EventType::KeyRelease(key))).unwrap();
+        self.sent.set(self.sent.get() + 2);
+        // 让出而不是等待计时器, 转发完成之前时钟不会前进
+        while self.backend.received() < self.sent.get() {
+            yield_now().await;
+        }
+    }
+
+    /// 等待脚本的运行状态变为 running
+    async fn wait_running(&self, running: bool) -> Vec<(String, bool)> {
+        for _ in 0..1000 {
+            let scripts = self.running().await;
+            if scripts.iter().all(|(_, r)| *r == running) {
+                return scripts;
+            }
+            yield_now().await;
+        }
+        self.running().await
+    }
+
+    /// 所有脚本的标题及是否运行中
+    async fn running(&self) -> Vec<(String, bool)> {
+        let (tx, rx) = oneshot::channel();
+        self.control.send(Control::Status(tx)).unwrap();
+        let snapshot = rx.await.unwrap();
+        snapshot
+            .scripts
+            .into_iter()
+            .map(|(s, _)| (s.title.to_string(), s.running()))
+            .collect()
+    }
+
+    /// 当前的层
+    async fn layers(&self) -> Vec<String> {
+        let (tx, rx) = oneshot::channel();
+        self.control.send(Control::Status(tx)).unwrap();
+        rx.await.unwrap().layers
+    }
+
+    /// 结束监听
+    async fn stop(self) {
+        drop(self.input);
+        let thread = self.thread;
+        tokio::task::spawn_blocking(move || thread.join().unwrap())
+            .await
+            .unwrap();
+    }
+}
+
+/// 用记录的后端运行脚本列表
+fn listen(scripts: &str, runtime: impl FnOnce(Runtime) -> Runtime) -> Listening {
+    run(config("", scripts), runtime)
+}
+
+/// 解析配置, global 为额外的全局配置, 需要在 [blocks] 之前
+fn config(global: &str, scripts: &str) -> Config {
+    toml::from_str(&format!("{global}\n{HEADER}{scripts}")).unwrap()
+}
+
+fn run(config: Config, runtime: impl FnOnce(Runtime) -> Runtime) -> Listening {
+    run_on(config, RecordBackend::new(), runtime)
+}
+
+/// 用指定的后端运行
+fn run_on(
+    config: Config,
+    (backend, input): (RecordBackend, Sender<Event>),
+    runtime: impl FnOnce(Runtime) -> Runtime,
+) -> Listening {
+    let (updater, _) = mpsc::unbounded_channel();
+    let (notifier, _) = mpsc::unbounded_channel();
+    let list = config.build(&updater, &notifier).unwrap();
+
+    let backend = Arc::new(backend);
+    let runtime = runtime(Runtime::new(backend.clone()));
+    let (control, rx) = mpsc::unbounded_channel();
+    // 监听一直阻塞, 不用 spawn_blocking, 否则运行中的阻塞任务会让暂停的时钟不再自动前进
+    let handle = Handle::current();
+    let thread = thread::spawn(move || {
+        let _guard = handle.enter();
+        list.listening(runtime, rx).unwrap()
+    });
+    Listening { backend, input, sent: Cell::new(0), control, thread }
+}
+
+fn event(event_type: EventType) -> Event {
+    Event { time: SystemTime::now(), name: None, event_type }
+}
+
+/// 写入 rgb 的 png 图片
+fn write_png(path: &Path, frame: &Frame) {
+    let file = File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgb);
+    let data: Vec<u8> = frame.pixels.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+    encoder.write_header().unwrap().write_image_data(&data).unwrap();
+}
+
+/// 等待模拟的事件达到指定数量, 最多等待 2 秒(暂停的时钟)
+async fn wait_events(backend: &RecordBackend, len: usize) -> Vec<EventType> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while backend.event_types().len() < len && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    backend.event_types()
+}
+
+#[tokio::test(start_paused = true)]
+async fn trigger_runs_script_on_record_backend() {
+    let scripts = r#"
+[[scripts]]
+title = "A"
+repeat = 1
+trigger = [{ key = "F1" }]
+methods = [
+    { event = "Key", args = "KeyA" },
+    { event = "Sleep", args = 100 },
+    { event = "Move", args = [10, 20] },
+]
+"#;
+    let listening = listen(scripts, |runtime| runtime);
+
+    // 不是触发按键, 不会运行
+    listening.tap(Key::F2).await;
+    listening.tap(Key::F1).await;
+    let events = wait_events(&listening.backend, 3).await;
+    assert_eq!(
+        events,
+        vec![
+            EventType::KeyPress(Key::KeyA),
+            EventType::KeyRelease(Key::KeyA),
+            EventType::MouseMove { x: 10.0, y: 20.0 },
+        ]
+    );
+    let times: Vec<_> = listening.backend.events().into_iter().map(|(time, _)| time).collect();
+    assert_eq!(times[1] - times[0], Duration::ZERO);
+    assert_eq!(times[2] - times[1], Duration::from_millis(100));
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn endless_loop_without_await_can_be_stopped() {
+    let scripts = r#"
+[vars]
+n = 0
+
+[[scripts]]
+title = "空循环"
+repeat = 0
+trigger = [{ key = "F1" }]
+methods = [{ event = "AddVar", args = ["n", 1] }]
+"#;
+    let listening = listen(scripts, |runtime| runtime);
+
+    // 脚本一直在运行, 时钟不会前进, 只能让出等待
+    listening.tap(Key::F1).await;
+    assert_eq!(listening.wait_running(true).await, vec![("空循环".to_string(), true)]);
+
+    // toggle 模式再按一次终止
+    listening.tap(Key::F1).await;
+    assert_eq!(listening.wait_running(false).await, vec![("空循环".to_string(), false)]);
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn click_image_path_is_relative_to_config() {
+    let dir = env::temp_dir().join(format!("kmm-config-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // 10x10 的黑色屏幕, (6, 4) 处有 2x2 的白色方块
+    let mut screen = Frame { width: 10, height: 10, pixels: vec![(0, 0, 0); 100] };
+    for (x, y) in [(6, 4), (7, 4), (6, 5), (7, 5)] {
+        screen.pixels[y * 10 + x] = (255, 255, 255);
+    }
+    let button = screen.crop(Region { x: 5, y: 3, width: 4, height: 4 }).unwrap();
+    write_png(&dir.join("button.png"), &button);
+    let scripts = r#"
+[[scripts]]
+title = "点击图片"
+repeat = 1
+trigger = [{ key = "F1" }]
+methods = [{ event = "ClickImage", args = { button = "Left", path = "button.png", threshold = 1.0 } }]
+"#;
+    fs::write(dir.join("config.toml"), format!("{HEADER}{scripts}")).unwrap();
+    // 工作目录不是配置所在的目录, 图片在生成脚本时读取
+    let config = Config::read(dir.join("config.toml")).unwrap();
+    let listening = run(config, |runtime| runtime.with_screen(Arc::new(ImageScreen(screen))));
+    fs::remove_dir_all(&dir).unwrap();
+
+    listening.tap(Key::F1).await;
+    let events = wait_events(&listening.backend, 3).await;
+    assert_eq!(
+        events,
+        vec![
+            EventType::MouseMove { x: 7.0, y: 5.0 },
+            EventType::ButtonPress(Button::Left),
+            EventType::ButtonRelease(Button::Left),
+        ]
+    );
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn sequence_is_interrupted_by_other_keys() {
+    let scripts = r#"
+[[scripts]]
+title = "gg"
+repeat = 1
+sequence = [{ key = "KeyG" }, { key = "KeyG" }]
+methods = [{ event = "Key", args = "KeyB" }]
+"#;
+    let listening = listen(scripts, |runtime| runtime);
+
+    // 中间按了其他键, 不会触发
+    for key in [Key::KeyG, Key::KeyA, Key::KeyG] {
+        listening.tap(key).await;
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(listening.backend.event_types(), vec![]);
+
+    listening.tap(Key::KeyG).await;
+    let events = wait_events(&listening.backend, 2).await;
+    assert_eq!(
+        events,
+        vec![EventType::KeyPress(Key::KeyB), EventType::KeyRelease(Key::KeyB)]
+    );
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn profile_follows_focused_window() {
+    let scripts = r#"
+[[profiles]]
+name = "游戏"
+class = "Steam_App_570"
+
+[[profiles]]
+name = "编辑器"
+title = "Code"
+process = "code"
+
+[[scripts]]
+title = "游戏中"
+repeat = 1
+trigger = [{ key = "F1" }]
+profiles = ["游戏"]
+swallow = true
+methods = [{ event = "Key", args = "KeyA" }]
+
+[[scripts]]
+title = "编辑器中"
+repeat = 1
+trigger = [{ key = "F1" }]
+profiles = ["编辑器"]
+methods = [{ event = "Key", args = "KeyB" }]
+"#;
+    let window = |class: &str, title: &str, process: &str| WindowInfo {
+        class: class.into(),
+        title: title.into(),
+        process: process.into(),
+    };
+    let focus = Arc::new(MockFocus::new(Some(window("firefox", "Code review", "firefox"))));
+    let listening = listen(scripts, |runtime| runtime.with_focus(focus.clone()));
+    let swallowed = || listening.backend.swallowed();
+
+    // 没有符合的方案, 都不会运行, 也不屏蔽
+    listening.tap(Key::F1).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(listening.backend.event_types(), vec![]);
+    assert_eq!(swallowed(), HashSet::new());
+
+    // 类名忽略大小写; 按下触发按键时重新查询焦点窗口
+    focus.set(Some(window("steam_app_570", "Dota 2", "dota2")));
+    listening.tap(Key::F1).await;
+    let events = wait_events(&listening.backend, 2).await;
+    assert_eq!(
+        events,
+        vec![EventType::KeyPress(Key::KeyA), EventType::KeyRelease(Key::KeyA)]
+    );
+    let f1 = Swallow { key: KeyOrButton::Key(Key::F1), modifiers: vec![] };
+    assert_eq!(swallowed(), HashSet::from([f1]));
+
+    // 标题包含且进程名相同
+    listening.backend.clear();
+    focus.set(Some(window("code", "main.rs - Code", "CODE")));
+    listening.tap(Key::F1).await;
+    let events = wait_events(&listening.backend, 2).await;
+    assert_eq!(
+        events,
+        vec![EventType::KeyPress(Key::KeyB), EventType::KeyRelease(Key::KeyB)]
+    );
+    assert_eq!(swallowed(), HashSet::new());
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn reload_resets_layers() {
+    let scripts = r#"
+[[scripts]]
+title = "切换层"
+repeat = 1
+trigger = [{ key = "F1" }]
+methods = [{ event = "Layer", args = ["push", "编辑"] }]
+"#;
+    let config = |layer: &str| config(&format!("layers = [{layer:?}]"), scripts);
+    let listening = run(config("默认"), |runtime| runtime);
+    assert_eq!(listening.layers().await, vec!["默认"]);
+    listening.tap(Key::F1).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(listening.layers().await, vec!["默认", "编辑"]);
+
+    let (updater, _) = mpsc::unbounded_channel();
+    let (notifier, _) = mpsc::unbounded_channel();
+    let list = config("新").build(&updater, &notifier).unwrap();
+    listening.control.send(Control::Reload(list)).unwrap();
+    assert_eq!(listening.layers().await, vec!["新"]);
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn seed_makes_runs_repeatable() {
+    let scripts = r#"
+[[scripts]]
+title = "随机"
+repeat = 1
+trigger = [{ key = "F1" }]
+jitter_px = 3
+methods = [
+    { event = "Move", args = [100, 100] },
+    { event = "Move", args = [200, 150, { curve = "bezier", duration = 50 }] },
+    { event = "Move", args = [300, 100, { curve = "linear", duration = 50, jitter = 5 }] },
+    { event = "Sleep", args = [1, 3] },
+    { event = "ClickOn", args = ["Left", 10, 10] },
+]
+"#;
+    let record = |seed: u64| async move {
+        let listening = run(config(&format!("seed = {seed}"), scripts), |runtime| runtime);
+        listening.tap(Key::F1).await;
+        let events = wait_events(&listening.backend, 14).await;
+        // 等脚本结束
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(events, listening.backend.event_types());
+        listening.stop().await;
+        events
+    };
+
+    let first = record(42).await;
+    assert_eq!(first.len(), 14);
+    assert_eq!(first, record(42).await);
+    assert_ne!(first, record(43).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn type_groups_characters_not_on_keyboard() {
+    let scripts = r#"
+[[scripts]]
+title = "A"
+repeat = 1
+trigger = [{ key = "F1" }]
+methods = [{ event = "Type", args = { text = "a世界" } }]
+"#;
+    let listening = listen(scripts, |r| r);
+    listening.tap(Key::F1).await;
+    let events = wait_events(&listening.backend, 2).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        events,
+        vec![EventType::KeyPress(Key::KeyA), EventType::KeyRelease(Key::KeyA)]
+    );
+    assert_eq!(listening.backend.typed(), "世界");
+    listening.stop().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn type_pastes_when_backend_has_no_unicode() {
+    let scripts = r#"
+[[scripts]]
+title = "A"
+repeat = 1
+trigger = [{ key = "F1" }]
+methods = [{ event = "Type", args = { text = "世界" } }]
+"#;
+    let clipboard = Arc::new(MemoryClipboard::new("old"));
+    let runtime = {
+        let clipboard = clipboard.clone();
+        move |r: Runtime| r.with_clipboard(clipboard)
+    };
+    let (backend, input) = RecordBackend::new();
+    let listening = run_on(config("", scripts), (backend.with_unicode(false), input), runtime);
+    listening.tap(Key::F1).await;
+    let events = wait_events(&listening.backend, 4).await;
+    assert_eq!(
+        events,
+        vec![
+            EventType::KeyPress(Key::ControlLeft),
+            EventType::KeyPress(Key::KeyV),
+            EventType::KeyRelease(Key::KeyV),
+            EventType::KeyRelease(Key::ControlLeft),
+        ]
+    );
+    assert_eq!(clipboard.get().unwrap(), "世界");
+    // 粘贴后还原之前的剪贴板
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(clipboard.get().unwrap(), "old");
+    assert_eq!(listening.backend.typed(), "");
+    listening.stop().await;
+}
+
+#[test]
+fn capture_and_timeout_require_wait() {
+    for (name, arg) in [("capture", r#"capture = "today""#), ("timeout", "timeout = 1000")] {
+        let scripts = format!(
+            r#"
+[[scripts]]
+title = "A"
+repeat = 1
+trigger = [{{ key = "F1" }}]
+methods = [{{ event = "Exec", args = {{ cmd = "date", wait = false, {arg} }} }}]
+"#
+        );
+        let (updater, _) = mpsc::unbounded_channel();
+        let (notifier, _) = mpsc::unbounded_channel();
+        let err = config("", &scripts).build(&updater, &notifier).err().unwrap();
+        assert!(err.to_string().contains(name), "{err}");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn start_records_repeat_methods_and_time() {
+    let scripts = r#"
+[[scripts]]
+title = "A"
+repeat = 3
+trigger = [{ key = "F1" }]
+methods = [{ event = "KeyDown", args = "KeyA" }, { event = "Sleep", args = 1000 }]
+"#;
+    let listening = listen(scripts, |r| r);
+    listening.tap(Key::F1).await;
+    wait_events(&listening.backend, 1).await;
+    let (pressed, _) = listening.backend.events().remove(0);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let (tx, rx) = oneshot::channel();
+    listening.control.send(Control::Status(tx)).unwrap();
+    let (status, _) = rx.await.unwrap().scripts.remove(0);
+    assert!(status.running());
+    assert_eq!((status.repeat, status.methods, status.method), (3, 2, 1));
+    // 开始后立即执行第一步
+    assert_eq!(status.started, Some(pressed));
+    assert_eq!(status.progress(), Some(0.5 / 3.0));
+    listening.stop().await;
+}